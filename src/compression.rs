@@ -0,0 +1,237 @@
+#[cfg(feature = "compression")]
+use std::collections::HashMap;
+
+use crate::error::{Error, Result};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression{
+    #[default]
+    None,
+    Yaz0, // Wii U/3DS, usually .szs
+    Zstd // Switch, .zs
+}
+
+impl Compression{
+    /// Guesses the compression from the first bytes of a file.
+    pub fn detect(data: &[u8]) -> Compression {
+        match data.get(0..4) {
+            Some(b"Yaz0") => Compression::Yaz0,
+            Some([0x28, 0xB5, 0x2F, 0xFD]) => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Guesses the compression from a file name, i.e. file.msbt.zs is Zstd.
+    pub fn from_filename(filename: &str) -> Compression {
+        let filename = filename.to_lowercase();
+        if filename.ends_with(".szs") {
+            Compression::Yaz0
+        } else if filename.ends_with(".zs") {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    /// Extension added after .msbt for files with this compression.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Yaz0 => ".szs",
+            Compression::Zstd => ".zs",
+        }
+    }
+}
+
+#[cfg(feature = "compression")]
+pub fn decompress(data: &[u8], compression: Compression) -> Result<Vec<u8>> {
+    match compression {
+        Compression::None => Ok(data.to_vec()),
+        Compression::Yaz0 => yaz0_decompress(data),
+        Compression::Zstd => Ok(zstd::stream::decode_all(data)?),
+    }
+}
+
+#[cfg(feature = "compression")]
+pub fn compress(data: Vec<u8>, compression: Compression) -> Result<Vec<u8>> {
+    match compression {
+        Compression::None => Ok(data),
+        Compression::Yaz0 => Ok(yaz0_compress(&data)),
+        Compression::Zstd => Ok(zstd::stream::encode_all(data.as_slice(), 0)?),
+    }
+}
+
+#[cfg(not(feature = "compression"))]
+pub fn decompress(data: &[u8], compression: Compression) -> Result<Vec<u8>> {
+    match compression {
+        Compression::None => Ok(data.to_vec()),
+        _ => Err(Error::CompressionDisabled),
+    }
+}
+
+#[cfg(not(feature = "compression"))]
+pub fn compress(data: Vec<u8>, compression: Compression) -> Result<Vec<u8>> {
+    match compression {
+        Compression::None => Ok(data),
+        _ => Err(Error::CompressionDisabled),
+    }
+}
+
+#[cfg(feature = "compression")]
+fn yaz0_decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let size = u32::from_be_bytes(data.get(4..8).ok_or_else(|| truncated(4))?.try_into().unwrap()) as usize;
+    // The size comes from the file, so the output only grows as it's written, and input running out is an error
+    let mut result = Vec::<u8>::new();
+    let mut pos = 0x10;
+    while result.len() < size {
+        let group = *data.get(pos).ok_or_else(|| truncated(pos))?;
+        pos += 1;
+        for bit in 0..8 {
+            if result.len() >= size {
+                break;
+            }
+            if group & (0x80 >> bit) != 0 { // Copy one byte as-is
+                result.push(*data.get(pos).ok_or_else(|| truncated(pos))?);
+                pos += 1;
+                continue;
+            }
+            //Back reference, 2 bytes (NR RR) or 3 bytes (0R RR NN) for long runs
+            let code = data.get(pos..pos+2).ok_or_else(|| truncated(pos))?;
+            let distance = (((code[0] & 0xF) as usize) << 8 | code[1] as usize) + 1;
+            let length = match code[0] >> 4 {
+                0 => {
+                    let length = *data.get(pos+2).ok_or_else(|| truncated(pos))? as usize + 0x12;
+                    pos += 3;
+                    length
+                }
+                n => {
+                    pos += 2;
+                    n as usize + 2
+                }
+            };
+            if distance > result.len() { // Points before the start of the output
+                return Err(Error::OffsetOutOfRange { section: "Yaz0".to_owned(), offset: pos as u64 });
+            }
+            let start = result.len() - distance;
+            for i in 0..length { // The source can overlap with what we're writing
+                result.push(result[start+i]);
+            }
+        }
+    }
+    result.truncate(size);
+    Ok(result)
+}
+
+#[cfg(feature = "compression")]
+fn truncated(offset: usize) -> Error {
+    Error::Truncated { section: "Yaz0".to_owned(), offset: offset as u64 }
+}
+
+#[cfg(feature = "compression")]
+fn yaz0_compress(data: &[u8]) -> Vec<u8> {
+    const WINDOW: usize = 0x1000;
+    const MAX_LENGTH: usize = 0x111;
+    const MAX_CANDIDATES: usize = 64;
+    let mut result = b"Yaz0".to_vec();
+    result.append(&mut (data.len() as u32).to_be_bytes().to_vec());
+    result.append(&mut vec![0,0,0,0,0,0,0,0]);
+    //Positions of every 3 byte sequence seen so far, to find back references quickly
+    let mut seen = HashMap::<[u8;3], Vec<usize>>::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let group_pos = result.len();
+        result.push(0);
+        for bit in 0..8 {
+            if pos >= data.len() {
+                break;
+            }
+            let max_length = MAX_LENGTH.min(data.len()-pos);
+            let mut best = (0, 0); // (length, distance)
+            if max_length >= 3 {
+                if let Some(candidates) = seen.get(&[data[pos], data[pos+1], data[pos+2]]) {
+                    for &candidate in candidates.iter().rev().take(MAX_CANDIDATES) {
+                        if pos-candidate > WINDOW {
+                            break;
+                        }
+                        let length = (0..max_length).take_while(|i| data[candidate+i] == data[pos+i]).count();
+                        if length > best.0 {
+                            best = (length, pos-candidate);
+                        }
+                    }
+                }
+            }
+            let (length, distance) = best;
+            if length < 3 {
+                result[group_pos] |= 0x80 >> bit;
+                result.push(data[pos]);
+                remember(&mut seen, data, pos);
+                pos += 1;
+                continue;
+            }
+            let distance = distance-1;
+            if length >= 0x12 {
+                result.push((distance >> 8) as u8);
+                result.push(distance as u8);
+                result.push((length-0x12) as u8);
+            } else {
+                result.push(((length-2) << 4 | distance >> 8) as u8);
+                result.push(distance as u8);
+            }
+            for i in pos..pos+length {
+                remember(&mut seen, data, i);
+            }
+            pos += length;
+        }
+    }
+    result
+}
+
+#[cfg(feature = "compression")]
+fn remember(seen: &mut HashMap<[u8;3], Vec<usize>>, data: &[u8], pos: usize) {
+    if pos+3 <= data.len() {
+        seen.entry([data[pos], data[pos+1], data[pos+2]]).or_default().push(pos);
+    }
+}
+
+#[cfg(all(test, feature = "compression"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn yaz0_round_trip() {
+        let mut noise = Vec::<u8>::new();
+        let mut state = 1u32;
+        for _i in 0..0x3000 {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            noise.push((state >> 16) as u8);
+        }
+        let mut runs = vec![7u8;0x500];
+        runs.extend_from_slice(b"abcabcabcabc");
+        runs.extend_from_slice(&noise[..0x1200]);
+        runs.extend_from_slice(&noise[..0x1200]); // Too far back for the window to reach the start
+        for data in [Vec::<u8>::new(), b"a".to_vec(), noise, runs] {
+            let compressed = yaz0_compress(&data);
+            assert_eq!(Compression::detect(&compressed), Compression::Yaz0);
+            assert_eq!(yaz0_decompress(&compressed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn yaz0_back_reference() {
+        let mut data = b"Yaz0".to_vec();
+        data.extend_from_slice(&10u32.to_be_bytes());
+        data.extend_from_slice(&[0u8;8]);
+        // 3 literals, then 7 bytes from 3 bytes back
+        data.extend_from_slice(&[0xE0, b'a', b'b', b'c', 0x50, 0x02]);
+        assert_eq!(yaz0_decompress(&data).unwrap(), b"abcabcabca");
+    }
+
+    #[test]
+    fn yaz0_size_isnt_trusted() {
+        let mut data = b"Yaz0".to_vec();
+        data.extend_from_slice(&0xFFFFFFF0u32.to_be_bytes());
+        data.extend_from_slice(&[0u8;8]);
+        data.extend_from_slice(&[0xFF, b'M', b's', b'g']);
+        assert!(matches!(yaz0_decompress(&data), Err(Error::Truncated { offset: 0x14, .. })));
+    }
+}
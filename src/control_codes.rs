@@ -2,7 +2,10 @@ use std::collections::VecDeque;
 
 use bytestream::ByteOrder;
 
-use crate::structs::txt2::{convert_char, read_char};
+use crate::error::{Error, Result};
+use crate::msbp::MSBP;
+use crate::structs::Encoding;
+use crate::structs::txt2::{convert_char, pop_bytes, read_char};
 
 #[derive(Debug)]
 pub struct ControlCode{
@@ -12,90 +15,162 @@ pub struct ControlCode{
     params: Vec<u8>
 }
 
-pub fn convert_control_code_binary(string: &mut VecDeque<u8>, order: bytestream::ByteOrder) -> String{
+/// Tags the project knows about are written with their names, the others as RawCmd.
+pub fn convert_control_code_binary(string: &mut VecDeque<u8>, order: bytestream::ByteOrder, encoding: Encoding, project: Option<&MSBP>) -> Result<String>{
+    let (tag_group, tag_type, params) = read_control_code(string, order)?;
+    Ok(write_control_code(tag_group, tag_type, &params, order, encoding, project))
+}
+
+pub fn convert_control_code_close_binary(string: &mut VecDeque<u8>, order: bytestream::ByteOrder, encoding: Encoding, project: Option<&MSBP>) -> Result<String> {
+    let (tag_group, tag_type) = read_control_code_close(string, order)?;
+    Ok(write_control_code_close(tag_group, tag_type, order, encoding, project))
+}
 
+/// Reads the group, type and parameters of a tag, right after its 0x0E.
+pub fn read_control_code(string: &mut VecDeque<u8>, order: bytestream::ByteOrder) -> Result<(u16, u16, Vec<u8>)> {
     let mut control_code = ControlCode {tag_group:0,tag_type:0,params_size:0,params:Vec::<u8>::new()};
 
     //Reading the group of the code
-    let byte_temp = [string.pop_front().unwrap(), string.pop_front().unwrap()];
-    let byte = read_char(byte_temp, order);
+    let byte = read_code_u16(string, order)?;
     control_code.tag_group = byte;
 
     //Reading the type of the code
-    let byte_temp = [string.pop_front().unwrap(), string.pop_front().unwrap()];
-    let byte = read_char(byte_temp, order);
+    let byte = read_code_u16(string, order)?;
     control_code.tag_type = byte;
 
     //Reading the amount of arguments
-    let byte_temp = [string.pop_front().unwrap(), string.pop_front().unwrap()];
-    let byte = read_char(byte_temp, order);
+    let byte = read_code_u16(string, order)?;
     control_code.params_size = byte;
 
     //Reading the arguments
-    for _i in 0..control_code.params_size {
-        control_code.params.push(string.pop_front().unwrap());
+    if string.len() < control_code.params_size as usize {
+        return Err(Error::TruncatedControlCode { offset: 0 });
     }
-    match control_code.tag_group {
-        0 => write_global(control_code),
-        _ => write_raw_code(control_code)
-    }
-    // Now we write the final string
+    control_code.params = string.drain(..control_code.params_size as usize).collect();
+    Ok((control_code.tag_group, control_code.tag_type, control_code.params))
 }
 
-pub fn convert_control_code_close_binary(string: &mut VecDeque<u8>, order: bytestream::ByteOrder) -> String {
-
-    let mut control_code = ControlCode {tag_group:0,tag_type:0,params_size:0,params:Vec::<u8>::new()};
-
+/// Reads the group and type of a closing tag, right after its 0x0F.
+pub fn read_control_code_close(string: &mut VecDeque<u8>, order: bytestream::ByteOrder) -> Result<(u16, u16)> {
     //Reading the group of the code
-    let byte_temp = [string.pop_front().unwrap(), string.pop_front().unwrap()];
-    let byte = read_char(byte_temp, order);
-    control_code.tag_group = byte;
+    let tag_group = read_code_u16(string, order)?;
 
     //Reading the type of the code
-    let byte_temp = [string.pop_front().unwrap(), string.pop_front().unwrap()];
-    let byte = read_char(byte_temp, order);
-    control_code.tag_type = byte;
-    
-    // Now we write the final string
+    let tag_type = read_code_u16(string, order)?;
+    Ok((tag_group, tag_type))
+}
+
+/// Markup for a tag, i.e. [RawCmd 0.3 E4_00_00_FF]
+pub fn write_control_code(tag_group: u16, tag_type: u16, params: &[u8], order: bytestream::ByteOrder, encoding: Encoding, project: Option<&MSBP>) -> String {
+    if let Some(named) = project.and_then(|p| p.decode_tag(tag_group, tag_type, params, order, encoding)) {
+        return named;
+    }
+    let control_code = ControlCode {tag_group, tag_type, params_size: params.len() as u16, params: params.to_vec()};
+    match control_code.tag_group {
+        0 => write_global(control_code, order, encoding),
+        _ => write_raw_code(control_code)
+    }
+}
+
+/// Markup for a closing tag, named like its opening tag when possible, i.e. [/Colour] or [/RawCmd 4.5]
+pub fn write_control_code_close(tag_group: u16, tag_type: u16, order: bytestream::ByteOrder, encoding: Encoding, project: Option<&MSBP>) -> String {
+    let named = match project.and_then(|p| p.decode_tag_close(tag_group, tag_type)) {
+        Some(named) => Some(named),
+        None => match (tag_group, tag_type) {
+            (0, 0) => Some("[/Ruby]".to_owned()),
+            (0, 1) => Some("[/Font]".to_owned()),
+            (0, 2) => Some("[/Size]".to_owned()),
+            (0, 3) => Some("[/Colour]".to_owned()),
+            _ => None
+        }
+    };
+    // A project could use one of our names for another tag, so check it comes back the same
+    if let Some(named) = named {
+        if convert_control_code_close(&named, order, encoding, project) == Some(write_control_code_close_binary(tag_group, tag_type, order, encoding)) {
+            return named;
+        }
+    }
     let mut control_string = String::from("[/RawCmd ");
-    control_string += &control_code.tag_group.to_string();
+    control_string += &tag_group.to_string();
     control_string += ".";
-    control_string += &control_code.tag_type.to_string();
+    control_string += &tag_type.to_string();
     control_string += "]";
 
     control_string
 }
 
-pub fn convert_control_code(code: &str, order: bytestream::ByteOrder) -> Vec<u8>{
+/// Binary form of a tag, from its 0x0E to the end of its parameters.
+pub fn write_control_code_binary(tag_group: u16, tag_type: u16, params: &[u8], order: bytestream::ByteOrder, encoding: Encoding) -> Vec<u8> {
+    let mut raw_bytes = convert_char('\u{0E}', order, encoding);
+    match order{
+        ByteOrder::BigEndian => {
+            raw_bytes.append(&mut tag_group.to_be_bytes().to_vec());
+            raw_bytes.append(&mut tag_type.to_be_bytes().to_vec());
+            raw_bytes.append(&mut (params.len() as u16).to_be_bytes().to_vec());
+        },
+        ByteOrder::LittleEndian => {
+            raw_bytes.append(&mut tag_group.to_le_bytes().to_vec());
+            raw_bytes.append(&mut tag_type.to_le_bytes().to_vec());
+            raw_bytes.append(&mut (params.len() as u16).to_le_bytes().to_vec());
+        },
+    }
+    raw_bytes.append(&mut params.to_vec());
+    raw_bytes
+}
+
+/// Binary form of a closing tag.
+pub fn write_control_code_close_binary(tag_group: u16, tag_type: u16, order: bytestream::ByteOrder, encoding: Encoding) -> Vec<u8> {
+    let mut raw_bytes = convert_char('\u{0F}', order, encoding);
+    match order{
+        ByteOrder::BigEndian => {
+            raw_bytes.append(&mut tag_group.to_be_bytes().to_vec());
+            raw_bytes.append(&mut tag_type.to_be_bytes().to_vec());
+        },
+        ByteOrder::LittleEndian => {
+            raw_bytes.append(&mut tag_group.to_le_bytes().to_vec());
+            raw_bytes.append(&mut tag_type.to_le_bytes().to_vec());
+        },
+    }
+    raw_bytes
+}
+
+/// Converts a tag back to binary. Returns None if it's one of ours but can't be read,
+/// i.e. a RawCmd with bad hex. Other names are left as text.
+pub fn convert_control_code(code: &str, order: bytestream::ByteOrder, encoding: Encoding) -> Option<Vec<u8>>{
     let mut bare_code = code.to_string();
     bare_code.remove(0);
     bare_code.pop();
     let bare_content = bare_code.split(' ').collect::<Vec<&str>>();
     match bare_content[0] {
         "RawCmd" => {
-            if bare_content.len() > 2 {
-                convert_raw_code(bare_content[1], bare_content[2], order)
+            if bare_content.len() > 3 {
+                None
             } else {
-                convert_raw_code(bare_content[1], "", order)
+                convert_raw_code(bare_content.get(1)?, bare_content.get(2).unwrap_or(&""), order, encoding)
             }
         }
-        "Colour" | "Color" => {
-            convert_colour_code(bare_content[1], order)
+        "Colour" | "Color" if bare_content.len() == 2 => {
+            convert_colour_code(bare_content[1], order, encoding)
         }
-        "Size" => {
-            convert_size_code(bare_content[1], order)
+        "Size" if bare_content.len() == 2 => {
+            convert_size_code(bare_content[1], order, encoding)
         }
+        "Colour" | "Color" | "Size" => None,
+        "Ruby" | "Font" => convert_text_code(&bare_code, order, encoding),
         _ => {
             let mut raw_bytes = Vec::<u8>::new();
             for char in code.chars(){
-                raw_bytes.append(&mut convert_char(char, order));
+                raw_bytes.append(&mut convert_char(char, order, encoding));
             }
-            raw_bytes
+            Some(raw_bytes)
         }
     }
 }
 
-pub fn convert_control_code_close(code: &str, order: bytestream::ByteOrder) -> Vec<u8>{
+/// Converts a closing tag back to binary. Names can only come from the project, so with
+/// one, a name it doesn't have is None; without one, it's left as text. A RawCmd without
+/// a proper group.type is None too.
+pub fn convert_control_code_close(code: &str, order: bytestream::ByteOrder, encoding: Encoding, project: Option<&MSBP>) -> Option<Vec<u8>>{
     let mut raw_bytes = Vec::<u8>::new();
     let mut bare_code = code.to_string();
     bare_code.remove(0);
@@ -104,12 +179,12 @@ pub fn convert_control_code_close(code: &str, order: bytestream::ByteOrder) -> V
     let bare_content = bare_code.split(' ').collect::<Vec<&str>>();
     match bare_content[0]{
         "RawCmd" => {
-            let code_def = bare_content[1].split('.').collect::<Vec<&str>>();
-            let tag_group: u16 = code_def[0].parse().unwrap();
-            let tag_type: u16 = code_def[1].parse().unwrap();
+            if bare_content.len() != 2 {
+                return None;
+            }
+            let (tag_group, tag_type) = raw_cmd_type(bare_content[1])?;
 
-            raw_bytes.push(0x0F);
-            raw_bytes.push(0x00);
+            raw_bytes.append(&mut convert_char('\u{0F}', order, encoding));
             match order{
                 ByteOrder::BigEndian => {
                     raw_bytes.append(&mut tag_group.to_be_bytes().to_vec());
@@ -121,19 +196,34 @@ pub fn convert_control_code_close(code: &str, order: bytestream::ByteOrder) -> V
                 },
             }
         }
+        "Ruby" if bare_content.len() == 1 => raw_bytes = write_control_code_close_binary(0, 0, order, encoding),
+        "Font" if bare_content.len() == 1 => raw_bytes = write_control_code_close_binary(0, 1, order, encoding),
+        "Size" if bare_content.len() == 1 => raw_bytes = write_control_code_close_binary(0, 2, order, encoding),
+        "Colour" | "Color" if bare_content.len() == 1 => raw_bytes = write_control_code_close_binary(0, 3, order, encoding),
         _=> {
-            for char in code.chars(){
-                raw_bytes.append(&mut convert_char(char, order));
+            match project.map(|p| p.find_tag_by_name(bare_content[0])) {
+                Some(Some((tag_group, tag_type, _))) if bare_content.len() == 1 => raw_bytes = write_control_code_close_binary(tag_group, tag_type, order, encoding),
+                Some(None) if bare_content.len() == 1 => return None,
+                _ => {
+                    for char in code.chars(){
+                        raw_bytes.append(&mut convert_char(char, order, encoding));
+                    }
+                }
             }
         }
     }
-    raw_bytes
+    Some(raw_bytes)
+}
+
+fn raw_cmd_type(code_def: &str) -> Option<(u16, u16)> {
+    let (tag_group, tag_type) = code_def.split_once('.')?;
+    Some((tag_group.parse().ok()?, tag_type.parse().ok()?))
 }
 
-fn write_global(code: ControlCode) -> String{
+fn write_global(code: ControlCode, order: bytestream::ByteOrder, encoding: Encoding) -> String{
     match code.tag_type {
-        0 => "[Ruby_to_document]".to_owned(), // Ruby type, string argument?
-        1 => "[Font_to_document]".to_owned(), // Font type, string argument?
+        0 => write_ruby_code(code, order, encoding), // Ruby type. Arguments u16 base length, then the ruby text
+        1 => write_font_code(code, order, encoding), // Font type. Argument font name
         2 => write_size_code(code), // Text size type. Argument u16?
         3 => write_colour_code(code), // Colour type. Argument #RRGGBBAA
         _ => write_raw_code(code).to_owned() //Unknown type. All types are hopefully handled above.
@@ -157,6 +247,73 @@ fn write_raw_code(code: ControlCode) -> String{
     control_string
 }
 
+// [Ruby 2 "ふりがな"]: the ruby text goes over the 2 next units of text
+fn write_ruby_code(code: ControlCode, order: bytestream::ByteOrder, encoding: Encoding) -> String {
+    if code.params.len() >= 4 {
+        let base_length = read_u16([code.params[0], code.params[1]], order);
+        let text_size = read_u16([code.params[2], code.params[3]], order) as usize;
+        if code.params.len() == 4 + text_size {
+            if let Some(text) = read_text(&code.params[4..], order, encoding) {
+                if ruby_params(base_length, &text, order, encoding) == code.params {
+                    return format!("[Ruby {} \"{}\"]", base_length, text);
+                }
+            }
+        }
+    }
+    write_raw_code(code)
+}
+
+// [Font "name"]
+fn write_font_code(code: ControlCode, order: bytestream::ByteOrder, encoding: Encoding) -> String {
+    if code.params.len() >= 2 {
+        let text_size = read_u16([code.params[0], code.params[1]], order) as usize;
+        if code.params.len() == 2 + text_size {
+            if let Some(text) = read_text(&code.params[2..], order, encoding) {
+                if font_params(&text, order, encoding) == code.params {
+                    return format!("[Font \"{}\"]", text);
+                }
+            }
+        }
+    }
+    write_raw_code(code)
+}
+
+// Text in quotes can't hold quotes itself, those tags stay raw
+fn read_text(bytes: &[u8], order: bytestream::ByteOrder, encoding: Encoding) -> Option<String> {
+    let mut bytes: VecDeque<u8> = bytes.iter().copied().collect();
+    let mut text = String::new();
+    while !bytes.is_empty() {
+        text.push(read_char(&mut bytes, order, encoding).ok()?);
+    }
+    if text.contains('"') {
+        return None;
+    }
+    Some(text)
+}
+
+fn write_text(text: &str, order: bytestream::ByteOrder, encoding: Encoding) -> Vec<u8> {
+    let mut raw_text: Vec<u8> = text.chars().flat_map(|c| convert_char(c, order, encoding)).collect();
+    let mut raw_bytes = match order {
+        ByteOrder::BigEndian => (raw_text.len() as u16).to_be_bytes().to_vec(),
+        ByteOrder::LittleEndian => (raw_text.len() as u16).to_le_bytes().to_vec(),
+    };
+    raw_bytes.append(&mut raw_text);
+    raw_bytes
+}
+
+fn ruby_params(base_length: u16, text: &str, order: bytestream::ByteOrder, encoding: Encoding) -> Vec<u8> {
+    let mut params = match order {
+        ByteOrder::BigEndian => base_length.to_be_bytes().to_vec(),
+        ByteOrder::LittleEndian => base_length.to_le_bytes().to_vec(),
+    };
+    params.append(&mut write_text(text, order, encoding));
+    params
+}
+
+fn font_params(text: &str, order: bytestream::ByteOrder, encoding: Encoding) -> Vec<u8> {
+    write_text(text, order, encoding)
+}
+
 fn write_size_code(code: ControlCode) -> String {
     let mut control_string = String::from("[Size ");
         for code in code.params{
@@ -167,6 +324,10 @@ fn write_size_code(code: ControlCode) -> String {
 }
 
 fn write_colour_code(code: ControlCode) -> String {
+    // Colours are read back as 4 bytes, anything else has to stay raw
+    if code.params.len() != 4 {
+        return write_raw_code(code);
+    }
     let mut control_string = String::from("[Colour #");
         for code in code.params{
             control_string += &format!("{code:02X}");
@@ -175,7 +336,7 @@ fn write_colour_code(code: ControlCode) -> String {
     control_string
 }
 
-fn convert_raw_code(code_type: &str, args: &str, order: bytestream::ByteOrder) -> Vec<u8>{
+fn convert_raw_code(code_type: &str, args: &str, order: bytestream::ByteOrder, encoding: Encoding) -> Option<Vec<u8>>{
     let mut raw_bytes = Vec::<u8>::new();
 
     let mut control_code = ControlCode{ 
@@ -184,20 +345,17 @@ fn convert_raw_code(code_type: &str, args: &str, order: bytestream::ByteOrder) -
         params_size: 0, 
         params: Vec::<u8>::new() 
     };
-    let code_def = code_type.split('.').collect::<Vec<&str>>();
-    control_code.tag_group = code_def[0].parse().unwrap();
-    control_code.tag_type = code_def[1].parse().unwrap();
+    let (tag_group, tag_type) = code_type.split_once('.')?;
+    control_code.tag_group = tag_group.parse().ok()?;
+    control_code.tag_type = tag_type.parse().ok()?;
 
     if !args.is_empty(){
-        control_code.params_size = ((args.len()+1)/3) as u16;
         for byte in args.split('_').collect::<Vec<&str>>() {
-            control_code.params.push(u8::from_str_radix(byte, 16).unwrap());
+            control_code.params.push(u8::from_str_radix(byte, 16).ok()?);
         }
-    } else {
-        control_code.params_size = 0u16;
     }
-    raw_bytes.push(0x0E);
-    raw_bytes.push(0x00);
+    control_code.params_size = control_code.params.len() as u16;
+    raw_bytes.append(&mut convert_char('\u{0E}', order, encoding));
     match order{
         ByteOrder::BigEndian => {
             raw_bytes.append(&mut control_code.tag_group.to_be_bytes().to_vec());
@@ -213,28 +371,23 @@ fn convert_raw_code(code_type: &str, args: &str, order: bytestream::ByteOrder) -
     if control_code.params_size != 0{
         raw_bytes.append(&mut control_code.params);
     }
-    raw_bytes
+    Some(raw_bytes)
 }
 
-fn convert_colour_code(args: &str, order: bytestream::ByteOrder) -> Vec<u8>{
+fn convert_colour_code(args: &str, order: bytestream::ByteOrder, encoding: Encoding) -> Option<Vec<u8>>{
     let mut raw_bytes = Vec::<u8>::new();
-    let mut bare_args = args.to_string();
-    bare_args.remove(0);
 
     let mut control_code = ControlCode{ 
         tag_group: 0, 
         tag_type: 3, 
         params_size: 4, 
-        params: Vec::<u8>::new() 
+        params: parse_hex_pairs(args.strip_prefix('#')?)?
     };
+    if control_code.params.len() != 4 {
+        return None;
+    }
 
-        for _i in 0..bare_args.len()/2{
-            let arg:String = bare_args.chars().skip(control_code.params.len()*2).take(2).collect();
-            control_code.params.push(u8::from_str_radix(&arg, 16).unwrap())
-        }
-
-    raw_bytes.push(0x0E);
-    raw_bytes.push(0x00);
+    raw_bytes.append(&mut convert_char('\u{0E}', order, encoding));
     match order{
         ByteOrder::BigEndian => {
             raw_bytes.append(&mut control_code.tag_group.to_be_bytes().to_vec());
@@ -250,28 +403,22 @@ fn convert_colour_code(args: &str, order: bytestream::ByteOrder) -> Vec<u8>{
     if control_code.params_size != 0{
         raw_bytes.append(&mut control_code.params);
     }
-    raw_bytes
+    Some(raw_bytes)
 }
 
-fn convert_size_code(args: &str, order: bytestream::ByteOrder) -> Vec<u8>{
+fn convert_size_code(args: &str, order: bytestream::ByteOrder, encoding: Encoding) -> Option<Vec<u8>>{
     let mut raw_bytes = Vec::<u8>::new();
-    let mut bare_args = args.to_string();
-    bare_args.remove(0);
 
+    // Written without a #, unlike colours
     let mut control_code = ControlCode{ 
         tag_group: 0, 
         tag_type: 2, 
-        params_size: 2, 
-        params: Vec::<u8>::new() 
+        params_size: 0, 
+        params: parse_hex_pairs(args)?
     };
+    control_code.params_size = control_code.params.len() as u16;
 
-        for _i in 0..bare_args.len()/2{
-            let arg:String = args.chars().skip(control_code.params.len()*2).take(2).collect();
-            control_code.params.push(u8::from_str_radix(&arg, 16).unwrap())
-        }
-
-    raw_bytes.push(0x0E);
-    raw_bytes.push(0x00);
+    raw_bytes.append(&mut convert_char('\u{0E}', order, encoding));
     match order{
         ByteOrder::BigEndian => {
             raw_bytes.append(&mut control_code.tag_group.to_be_bytes().to_vec());
@@ -287,5 +434,69 @@ fn convert_size_code(args: &str, order: bytestream::ByteOrder) -> Vec<u8>{
     if control_code.params_size != 0{
         raw_bytes.append(&mut control_code.params);
     }
-    raw_bytes
+    Some(raw_bytes)
+}
+
+// Hex written without separators, i.e. FF0000FF
+fn parse_hex_pairs(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i+2], 16).ok()).collect()
+}
+
+// Ruby and Font, which have a quoted text argument
+fn convert_text_code(bare_code: &str, order: bytestream::ByteOrder, encoding: Encoding) -> Option<Vec<u8>>{
+    let (name, args) = bare_code.split_once(' ')?;
+    let mut control_code = ControlCode{
+        tag_group: 0,
+        tag_type: 0,
+        params_size: 0,
+        params: Vec::<u8>::new()
+    };
+    match name {
+        "Ruby" => {
+            let (base_length, text) = args.split_once(' ')?;
+            let text = text.strip_prefix('"')?.strip_suffix('"')?;
+            control_code.params = ruby_params(base_length.parse().ok()?, text, order, encoding);
+        }
+        "Font" => {
+            let text = args.strip_prefix('"')?.strip_suffix('"')?;
+            control_code.tag_type = 1;
+            control_code.params = font_params(text, order, encoding);
+        }
+        _ => return None
+    }
+    control_code.params_size = control_code.params.len() as u16;
+
+    let mut raw_bytes = convert_char('\u{0E}', order, encoding);
+    match order{
+        ByteOrder::BigEndian => {
+            raw_bytes.append(&mut control_code.tag_group.to_be_bytes().to_vec());
+            raw_bytes.append(&mut control_code.tag_type.to_be_bytes().to_vec());
+            raw_bytes.append(&mut control_code.params_size.to_be_bytes().to_vec());
+        },
+        ByteOrder::LittleEndian => {
+            raw_bytes.append(&mut control_code.tag_group.to_le_bytes().to_vec());
+            raw_bytes.append(&mut control_code.tag_type.to_le_bytes().to_vec());
+            raw_bytes.append(&mut control_code.params_size.to_le_bytes().to_vec());
+        },
+    }
+    raw_bytes.append(&mut control_code.params);
+    Some(raw_bytes)
+}
+
+// Like strings, tags don't know where they start: Message::from_binary fills in the offset
+fn read_code_u16(string: &mut VecDeque<u8>, order: bytestream::ByteOrder) -> Result<u16>{
+    match pop_bytes(string) {
+        Ok(byte_temp) => Ok(read_u16(byte_temp, order)),
+        Err(_) => Err(Error::TruncatedControlCode { offset: 0 }),
+    }
+}
+
+fn read_u16(byte_temp: [u8;2], order: bytestream::ByteOrder) -> u16{
+    match order {
+        ByteOrder::BigEndian => u16::from_be_bytes(byte_temp),
+        ByteOrder::LittleEndian => u16::from_le_bytes(byte_temp),
+    }
 }
\ No newline at end of file
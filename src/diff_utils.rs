@@ -1,9 +1,14 @@
 use std::{fs::File, io::{BufReader, Lines}};
 
 use crate::msbt::MSBTString;
+use ::msbt::glyphs::Glyphs;
+use ::msbt::msbp::MSBP;
+use ::msbt::structs::{Encoding, TXT2};
+use log::debug;
 
 #[derive(Debug, Clone)]
 pub struct StringDiff{
+    file: String, // Path of the MSBT in the archive, empty when patching a lone MSBT
     state: State,
     label: String,
     string: String
@@ -48,8 +53,8 @@ pub fn get_edited(original: Vec<MSBTString>, vec_edited: Vec<Vec<MSBTString>>) -
     for edited in vec_edited{
         for string in edited {
             let index = original.iter().position(|s| s.label == string.label);
-            if index.is_some(){
-                let string_original = original.get(index.unwrap()).unwrap();
+            if let Some(index) = index{
+                let string_original = original.get(index).unwrap();
                 if string_original.string != string.string{
                     result.push(string);
                 }
@@ -61,14 +66,18 @@ pub fn get_edited(original: Vec<MSBTString>, vec_edited: Vec<Vec<MSBTString>>) -
 
 pub fn convert_diff(diff: Lines<BufReader<File>>) -> ::msbt::Result<Vec<StringDiff>> {
     let mut result = Vec::<StringDiff>::new();
-    let mut current_diff = StringDiff { state: State::Null, label: "".to_owned(), string: "".to_owned() };
+    let mut current_file = "".to_owned();
+    let mut current_diff = StringDiff { file: current_file.clone(), state: State::Null, label: "".to_owned(), string: "".to_owned() };
     for line in diff.map_while(Result::ok) {
         if line.is_empty() {
             if current_diff.state != State::Null{
                 current_diff.string = current_diff.string.trim().to_owned();
                 result.push(current_diff.clone());
-                current_diff = StringDiff { state: State::Null, label: "".to_owned(), string: "".to_owned() };
+                current_diff = StringDiff { file: current_file.clone(), state: State::Null, label: "".to_owned(), string: "".to_owned() };
             }
+        } else if current_diff.state == State::Null && line.starts_with('@') {
+                current_file = line[1..].to_owned();
+                current_diff.file = current_file.clone();
         } else if current_diff.state == State::Null {
                 let mut chars: Vec<char> = line.chars().collect();
                 match *chars.first().unwrap(){
@@ -79,7 +88,7 @@ pub fn convert_diff(diff: Lines<BufReader<File>>) -> ::msbt::Result<Vec<StringDi
                 }
                 chars.remove(0);
                 current_diff.label = chars.into_iter().collect();
-                println!("Found edit {}!", current_diff.label)
+                debug!("Found edit {}!", current_diff.label)
             } else {
                 let mut edited_line = line.clone();
                 edited_line.remove(0);
@@ -92,15 +101,31 @@ pub fn convert_diff(diff: Lines<BufReader<File>>) -> ::msbt::Result<Vec<StringDi
     Ok(result)
 }
 
-pub fn patch_diff(diff: Vec<StringDiff>, msbt: Vec<MSBTString>, order: bytestream::ByteOrder) -> ::msbt::Result<Vec<MSBTString>>{
+/// Archive paths the diff touches, in the order they appear.
+pub fn get_files(diff: &[StringDiff]) -> Vec<String> {
+    let mut result = Vec::<String>::new();
+    for string_diff in diff {
+        if string_diff.state != State::Null && !result.contains(&string_diff.file) {
+            result.push(string_diff.file.clone());
+        }
+    }
+    result
+}
+
+pub fn get_file_diff(diff: &[StringDiff], file: &str) -> Vec<StringDiff> {
+    diff.iter().filter(|d| d.file == file).cloned().collect()
+}
+
+/// Applies a diff. Texts are written with markup by diff, so they're read with the same project and glyphs.
+pub fn patch_diff(diff: Vec<StringDiff>, msbt: Vec<MSBTString>, order: bytestream::ByteOrder, encoding: Encoding, project: Option<&MSBP>, glyphs: &Glyphs) -> ::msbt::Result<Vec<MSBTString>>{
     let mut new_msbt = msbt.clone();
     for string_diff in diff {
-        println!("Patching {}...", string_diff.label);
+        debug!("Patching {}...", string_diff.label);
         let corrected_string = string_diff.string + "\0";
         match string_diff.state {
-            State::Added => ::msbt::msbt::add_string(&mut new_msbt, string_diff.label, corrected_string, order),
-            State::Deleted => ::msbt::msbt::delete_string_by_label(&mut new_msbt, string_diff.label),
-            State::Edited => ::msbt::msbt::edit_string_by_label(&mut new_msbt, string_diff.label, corrected_string, order),
+            State::Added => ::msbt::msbt::add_string_raw(&mut new_msbt, string_diff.label, TXT2::parse_string(&corrected_string, order, encoding, project, glyphs)?),
+            State::Deleted => ::msbt::msbt::delete_string_by_label(&mut new_msbt, string_diff.label)?,
+            State::Edited => ::msbt::msbt::edit_string_by_label_raw(&mut new_msbt, string_diff.label, TXT2::parse_string(&corrected_string, order, encoding, project, glyphs)?),
             State::Null => {},
         }
     }
@@ -0,0 +1,463 @@
+use std::collections::{BTreeMap, HashSet};
+use std::str::FromStr;
+
+use indexmap::IndexMap;
+use log::debug;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::glyphs::{Glyphs, Platform};
+use crate::msbp::MSBP;
+use crate::msbt::{self, MSBTString, MSBT};
+use crate::structs::{Encoding, UnknownSection, TXT2};
+
+/// Version of the documents written by `Document::to_string`. Version 1 is the flat TOML format, from before `meta`.
+pub const VERSION: u32 = 2;
+
+/// Text format a document is written in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format{
+    #[default]
+    Toml,
+    Json, // Needs the json feature
+    Yaml // Needs the yaml feature
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(format: &str) -> std::result::Result<Format, String> {
+        match format.to_lowercase().as_str() {
+            "toml" => Ok(Format::Toml),
+            "json" => Ok(Format::Json),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            _ => Err(format!("Unknown format {}, expected TOML, JSON or YAML", format))
+        }
+    }
+}
+
+impl Format{
+    /// Guesses the format from a file name, i.e. file.json is JSON.
+    pub fn from_filename(filename: &str) -> Option<Format> {
+        filename.rsplit_once('.').and_then(|(_, extension)| extension.parse().ok())
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Toml => "toml",
+            Format::Json => "json",
+            Format::Yaml => "yaml",
+        }
+    }
+}
+
+/// An MSBT as written by extract: a `meta` table describing the file, then one table per message.
+/// It's the same in every format.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Document {
+    pub meta: Meta,
+    #[serde(default)]
+    pub messages: Vec<MessageEntry>, // In TXT2 order
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Meta {
+    pub version: u32,
+    pub is_big_endian: bool,
+    #[serde(default)]
+    pub encoding: Encoding,
+    #[serde(default = "default_header_version")]
+    pub header_version: u8,
+    #[serde(default)]
+    pub has_attributes: bool,
+    #[serde(default = "default_has_labels")]
+    pub has_labels: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label_slots: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<Platform>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub attribute_strings: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attribute_table_start: Option<u32>, // Where the attribute strings are in ATR1, raw attributes point there
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unknown_sections: Vec<RawSection>,
+}
+
+/// A section that isn't parsed, with its position among all the sections.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RawSection {
+    pub position: usize,
+    pub magic: String,
+    pub data: String,
+}
+
+/// A message. Comments are only for translators, they aren't written to the MSBT.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MessageEntry {
+    pub label: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<u32>, // Messages without an index go after the others, in the order they're in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u32>,
+    pub text: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_terminator: bool, // For strings stored without a null at the end, i.e. empty ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub style: Option<u32>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub comment: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attributes: Option<Attributes>,
+}
+
+/// Attributes are raw bytes, or named fields when a project describes them.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Attributes {
+    Raw(String),
+    Fields(BTreeMap<String, String>),
+}
+
+// Read first, so that files from a newer version aren't reported as malformed
+#[derive(Deserialize)]
+struct DocumentVersion {
+    meta: Option<MetaVersion>,
+}
+
+#[derive(Deserialize)]
+struct MetaVersion {
+    version: u32,
+}
+
+// Version 1, only ever written as TOML
+#[derive(Deserialize, Clone)]
+struct FlatDocument {
+    is_big_endian: bool,
+    #[serde(default)]
+    encoding: Encoding,
+    has_attributes: bool,
+    #[serde(default = "default_has_labels")]
+    has_labels: bool,
+    #[serde(default)]
+    label_slots: Option<u32>,
+    #[serde(default)]
+    platform: Option<Platform>,
+    #[serde(default)]
+    attribute_strings: String,
+    #[serde(default)]
+    attribute_table_start: Option<u32>,
+    strings: IndexMap<String, String>,
+    #[serde(default)]
+    indices: IndexMap<String, u32>,
+    #[serde(default)]
+    attributes: IndexMap<String, Attributes>,
+    #[serde(default)]
+    ids: IndexMap<String, u32>,
+    #[serde(default)]
+    styles: IndexMap<String, u32>,
+}
+
+impl From<FlatDocument> for Document {
+    fn from(flat: FlatDocument) -> Self {
+        let messages = flat.strings.iter().map(|(label, text)| MessageEntry {
+            label: label.clone(),
+            index: flat.indices.get(label).copied(),
+            id: flat.ids.get(label).copied(),
+            text: text.clone(),
+            no_terminator: false,
+            style: flat.styles.get(label).copied(),
+            comment: String::new(),
+            attributes: flat.attributes.get(label).cloned(),
+        }).collect();
+        Document {
+            meta: Meta {
+                version: 1,
+                is_big_endian: flat.is_big_endian,
+                encoding: flat.encoding,
+                header_version: default_header_version(),
+                has_attributes: flat.has_attributes,
+                has_labels: flat.has_labels,
+                label_slots: flat.label_slots,
+                platform: flat.platform,
+                attribute_strings: flat.attribute_strings,
+                attribute_table_start: flat.attribute_table_start,
+                unknown_sections: Vec::<RawSection>::new(),
+            },
+            messages,
+        }
+    }
+}
+
+fn default_has_labels() -> bool {
+    true
+}
+
+fn default_header_version() -> u8 {
+    3
+}
+
+impl Document {
+    /// Describes an MSBT and its messages. With a project, tags and attributes are written with their names.
+    pub fn from_msbt(msbt: &MSBT, project: Option<&MSBP>, glyphs: &Glyphs) -> Result<Document> {
+        let mut messages = Vec::<MessageEntry>::new();
+        let string_table = msbt::get_attribute_strings(msbt);
+        let table_start = msbt::get_attribute_table_start(msbt);
+        for string in msbt.iter() {
+            let attributes = if string.attributes.is_empty() {
+                None
+            } else {
+                let fields = project.and_then(|p| p.decode_attributes(&string.attributes, &string_table, table_start, msbt.endianness(), msbt.encoding()));
+                Some(match fields {
+                    Some(fields) => Attributes::Fields(fields),
+                    None => Attributes::Raw(bytes_to_hex(&string.attributes)),
+                })
+            };
+            let mut parsed_string =
+                TXT2::parse_binary(string.string.clone(), msbt.endianness(), msbt.encoding(), project, glyphs).map_err(|err| err.in_message(&string.label))?;
+            let no_terminator = !parsed_string.ends_with('\0');
+            if !no_terminator {
+                parsed_string.truncate(parsed_string.len() - 1);
+            }
+            messages.push(MessageEntry {
+                label: string.label.clone(),
+                index: Some(string.index),
+                id: string.id,
+                text: parsed_string,
+                no_terminator,
+                style: string.style,
+                comment: String::new(),
+                attributes,
+            });
+        }
+        let unknown_sections = msbt::get_unknown_sections(msbt).into_iter().map(|(position, section)| RawSection {
+            position,
+            magic: String::from_utf8_lossy(&section.magic).into_owned(),
+            data: bytes_to_hex(&section.data),
+        }).collect();
+        Ok(Document {
+            meta: Meta {
+                version: VERSION,
+                is_big_endian: matches!(msbt.endianness(), bytestream::ByteOrder::BigEndian),
+                encoding: msbt.encoding(),
+                header_version: msbt::get_version(msbt),
+                has_attributes: msbt.has_attributes(),
+                has_labels: msbt.has_labels(),
+                label_slots: msbt::get_label_slots(msbt),
+                platform: glyphs.platform,
+                attribute_strings: bytes_to_hex(&string_table),
+                // Only matters when there are strings for the attributes to point at
+                attribute_table_start: Some(table_start).filter(|_| !string_table.is_empty()),
+                unknown_sections,
+            },
+            messages,
+        })
+    }
+
+    /// Builds the MSBT the document describes. The same project has to be given as to `from_msbt`.
+    pub fn to_msbt(&self, project: Option<&MSBP>, glyphs: &Glyphs) -> Result<MSBT> {
+        let meta = &self.meta;
+        let mut string_table = hex_to_bytes(&meta.attribute_strings)?;
+        // Raw records point where the table used to be, but named ones can point wherever it ends up
+        let table_start = meta.attribute_table_start.filter(|_| self.messages.iter().any(|m| matches!(m.attributes, Some(Attributes::Raw(_)))));
+        let strings = self.encode_strings(project, glyphs, &mut string_table, table_start)?;
+        let has_ids = self.messages.iter().any(|m| m.id.is_some());
+        let has_styles = self.messages.iter().any(|m| m.style.is_some());
+        let mut msbt = msbt::new(self.byte_order(), meta.encoding, meta.has_labels, has_ids, meta.has_attributes, has_styles);
+        msbt::set_version(&mut msbt, meta.header_version);
+        msbt::set_attribute_strings(&mut msbt, string_table, table_start);
+        for section in &meta.unknown_sections {
+            if section.magic.len() != 4 {
+                return Err(Error::InvalidSectionMagic(section.magic.clone()));
+            }
+            let data = hex_to_bytes(&section.data)?;
+            msbt::add_unknown_section(&mut msbt, section.position, UnknownSection::new(section.magic.as_bytes().to_vec(), data));
+        }
+        if let Some(slots) = meta.label_slots {
+            msbt::set_label_slots(&mut msbt, slots);
+        }
+        msbt::set_strings(&mut msbt, strings);
+        Ok(msbt)
+    }
+
+    /// The messages as stored in TXT2, in index order.
+    pub fn strings(&self, project: Option<&MSBP>, glyphs: &Glyphs) -> Result<Vec<MSBTString>> {
+        self.encode_strings(project, glyphs, &mut hex_to_bytes(&self.meta.attribute_strings)?, self.meta.attribute_table_start)
+    }
+
+    pub fn byte_order(&self) -> bytestream::ByteOrder {
+        match self.meta.is_big_endian {
+            true => bytestream::ByteOrder::BigEndian,
+            false => bytestream::ByteOrder::LittleEndian,
+        }
+    }
+
+    // Named attributes are encoded with the project, adding their strings to `string_table` if needed.
+    // `table_start` is where the table has to stay, if anywhere.
+    fn encode_strings(&self, project: Option<&MSBP>, glyphs: &Glyphs, string_table: &mut Vec<u8>, table_start: Option<u32>) -> Result<Vec<MSBTString>> {
+        let mut strings = Vec::<MSBTString>::new();
+        let order = self.byte_order();
+        let encoding = self.meta.encoding;
+        debug!("Parsing {} string(s)...", self.messages.len());
+        // Indices only give the order, so TXT2 has no gap even if some were edited out
+        let mut messages: Vec<&MessageEntry> = self.messages.iter().collect();
+        messages.sort_by_key(|m| m.index.unwrap_or(u32::MAX));
+        let mut labels = HashSet::<&str>::new();
+        for (i, message) in messages.into_iter().enumerate() {
+            let label = &message.label;
+            if !labels.insert(label) {
+                return Err(Error::DuplicateLabel(label.clone()));
+            }
+            let corrected_string = if message.no_terminator {message.text.to_owned()} else {message.text.to_owned() + "\0"};
+            let attributes = match &message.attributes {
+                Some(Attributes::Raw(attributes)) => hex_to_bytes(attributes).map_err(|err| err.in_message(label))?,
+                Some(Attributes::Fields(fields)) => {
+                    let project = project.ok_or(Error::MissingProject)?;
+                    let table_start = table_start.unwrap_or(8 + self.messages.len() as u32 * project.attribute_size());
+                    project.encode_attributes(fields, string_table, table_start, order, encoding).map_err(|err| err.in_message(label))?
+                }
+                None => Vec::<u8>::new(),
+            };
+            strings.push(MSBTString {
+                index: i as u32,
+                label: label.to_string(),
+                // Files without labels fall back on the label as ID
+                id: message.id.or_else(|| if self.meta.has_labels {None} else {label.parse().ok()}),
+                string: TXT2::parse_string(&corrected_string, order, encoding, project, glyphs).map_err(|err| err.in_message(label))?,
+                attributes,
+                style: message.style,
+            });
+        }
+        debug!("Parsed {} string(s).", strings.len());
+        Ok(strings)
+    }
+
+    pub fn to_string(&self, format: Format) -> Result<String> {
+        match format {
+            Format::Toml => toml::ser::to_string_pretty(self).map_err(|err| Error::Serialization(err.to_string())),
+            Format::Json => to_json(self),
+            Format::Yaml => to_yaml(self),
+        }
+    }
+
+    /// Reads a document. Files without `meta` are from version 1, and are converted to the current version.
+    pub fn from_str(string: &str, format: Format) -> Result<Document> {
+        let version: DocumentVersion = parse(string, format)?;
+        match version.meta {
+            None => Ok(parse::<FlatDocument>(string, format)?.into()),
+            Some(meta) if meta.version > VERSION => Err(Error::UnsupportedDocumentVersion(meta.version, VERSION)),
+            Some(_) => parse(string, format),
+        }
+    }
+}
+
+fn parse<T: DeserializeOwned>(string: &str, format: Format) -> Result<T> {
+    match format {
+        Format::Toml => Ok(toml::de::from_str(string)?),
+        Format::Json => from_json(string),
+        Format::Yaml => from_yaml(string),
+    }
+}
+
+#[cfg(feature = "json")]
+fn to_json(document: &Document) -> Result<String> {
+    let mut result = serde_json::to_string_pretty(document).map_err(|err| Error::Serialization(err.to_string()))?;
+    result.push('\n');
+    Ok(result)
+}
+
+#[cfg(feature = "json")]
+fn from_json<T: DeserializeOwned>(string: &str) -> Result<T> {
+    Ok(serde_json::from_str(string)?)
+}
+
+#[cfg(not(feature = "json"))]
+fn to_json(_document: &Document) -> Result<String> {
+    Err(Error::FormatDisabled("json"))
+}
+
+#[cfg(not(feature = "json"))]
+fn from_json<T: DeserializeOwned>(_string: &str) -> Result<T> {
+    Err(Error::FormatDisabled("json"))
+}
+
+#[cfg(feature = "yaml")]
+fn to_yaml(document: &Document) -> Result<String> {
+    serde_yaml::to_string(document).map_err(|err| Error::Serialization(err.to_string()))
+}
+
+#[cfg(feature = "yaml")]
+fn from_yaml<T: DeserializeOwned>(string: &str) -> Result<T> {
+    Ok(serde_yaml::from_str(string)?)
+}
+
+#[cfg(not(feature = "yaml"))]
+fn to_yaml(_document: &Document) -> Result<String> {
+    Err(Error::FormatDisabled("yaml"))
+}
+
+#[cfg(not(feature = "yaml"))]
+fn from_yaml<T: DeserializeOwned>(_string: &str) -> Result<T> {
+    Err(Error::FormatDisabled("yaml"))
+}
+
+// Raw bytes are written the same way as RawCmd arguments, i.e. 01_00_FF
+fn bytes_to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02X}")).collect::<Vec<String>>().join("_")
+}
+
+fn hex_to_bytes(hex: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::<u8>::new();
+    for byte in hex.split('_').filter(|b| !b.is_empty()) {
+        match u8::from_str_radix(byte, 16) {
+            Ok(byte) => bytes.push(byte),
+            Err(_) => return Err(Error::InvalidHex(byte.to_owned())),
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytestream::ByteOrder;
+    use super::*;
+
+    fn round_trip(msbt: &MSBT) -> MSBT {
+        let glyphs = Glyphs::default();
+        let text = Document::from_msbt(msbt, None, &glyphs).unwrap().to_string(Format::Toml).unwrap();
+        Document::from_str(&text, Format::Toml).unwrap().to_msbt(None, &glyphs).unwrap()
+    }
+
+    #[test]
+    fn strings_without_terminator() {
+        let mut msbt = msbt::new(ByteOrder::LittleEndian, Encoding::UTF8, true, false, false, false);
+        msbt.insert("Empty", Vec::<u8>::new()).unwrap();
+        msbt.insert("Cut", b"ab".to_vec()).unwrap();
+        msbt.insert("Full", b"ab\0".to_vec()).unwrap();
+        let read = round_trip(&msbt);
+        assert_eq!(read.to_binary().unwrap(), msbt.to_binary().unwrap());
+        assert!(read.get("Empty").unwrap().string.is_empty());
+    }
+
+    #[test]
+    fn attribute_strings_stay_put() {
+        let order = ByteOrder::LittleEndian;
+        let mut msbt = msbt::new(order, Encoding::UTF8, true, false, true, false);
+        // Both records point at the string table, right after them
+        let string = |index: u32, label: &str| MSBTString { index, label: label.to_owned(), id: None, string: b"a\0".to_vec(), attributes: 16u32.to_le_bytes().to_vec(), style: None };
+        msbt::set_strings(&mut msbt, vec![string(0, "A"), string(1, "B")]);
+        msbt::set_attribute_strings(&mut msbt, b"x\0".to_vec(), Some(16));
+        let file = msbt.to_binary().unwrap();
+        let read = msbt::from_binary(&mut std::io::Cursor::new(&file)).unwrap();
+        assert_eq!(round_trip(&read).to_binary().unwrap(), file);
+
+        // Without a message, the record left still points at the table
+        let glyphs = Glyphs::default();
+        let mut document = Document::from_msbt(&read, None, &glyphs).unwrap();
+        document.messages.retain(|m| m.label != "B");
+        let written = document.to_msbt(None, &glyphs).unwrap().to_binary().unwrap();
+        let read = msbt::from_binary(&mut std::io::Cursor::new(&written)).unwrap();
+        assert_eq!(msbt::get_attribute_strings(&read)[16-msbt::get_attribute_table_start(&read) as usize..], *b"x\0");
+    }
+}
@@ -16,18 +16,182 @@ pub enum Error {
     #[error("File given is not an MSBT file")]
     NotMSBT,
 
+    /// Error called when the file isn't a SARC archive
+    #[error("File given is not a SARC archive")]
+    NotSARC,
+
+    /// Error called when the file isn't an MSBP project file
+    #[error("File given is not an MSBP file")]
+    NotMSBP,
+
+    /// Error called when named attributes are used without a project file to describe them
+    #[error("Named attributes need a project file!")]
+    MissingProject,
+
+    /// Error called when a diff refers to a file the archive doesn't have
+    #[error("{0} isn't in the archive!")]
+    MissingArchiveFile(String),
+
+    /// Error called when a file in an archive has a name that would be extracted outside of the output folder
+    #[error("{0} can't be extracted, its path leaves the output folder!")]
+    UnsafeArchivePath(String),
+
+    /// Error called when a string is looked up by a label the file doesn't have
+    #[error("No label named {0} found!")]
+    MissingLabel(String),
+
+    /// Error called when a string is looked up by an index TXT2 doesn't have
+    #[error("No string at index {0}!")]
+    MissingString(u32),
+
+    /// Error called when a string is added or renamed with a label that's already used
+    #[error("Label {0} is already used!")]
+    DuplicateLabel(String),
+
+    /// Error called when a profile defines the same tag twice
+    #[error("Tag {0}.{1} is defined twice in the profile!")]
+    DuplicateTag(u16, u16),
+
+    /// Error called when a custom glyph has a name that can't be escaped, or isn't a private use character
+    #[error("Glyph {0} is invalid! Names are made of letters, digits and underscores, and codes go from 0xE000 to 0xF8FF.")]
+    InvalidGlyph(String),
+
     /// Error called when there's an error in the file
     #[error("File given is malformed!")]
     MalformedFile,
 
+    /// Error called when adding strings would move the ATR1 string table, which the attribute records point into
+    #[error("Attribute strings would move from {from:#x} to {to:#x}, away from where the attributes point!")]
+    AttributeStringsMoved { from: u32, to: u32 },
+
+    /// Error called when a file without labels has a string with neither a numeric label nor an ID
+    #[error("String \"{0}\" needs a numeric label or an ID, the file has no labels!")]
+    MissingId(String),
+
+    /// Error called when a file lacks a section it can't do without
+    #[error("File has no {0} section!")]
+    MissingSection(String),
+
+    /// Error called when the header gives an encoding that isn't UTF-8, UTF-16 or UTF-32
+    #[error("Unknown encoding {0:#x}!")]
+    UnknownEncoding(u8),
+
+    /// Error called when a project gives a parameter or attribute a type that doesn't exist
+    #[error("Unknown parameter type {0:#x}!")]
+    UnknownParameterType(u8),
+
+    /// Error called when a project section refers to an entry of another section that isn't there
+    #[error("{section} refers to entry {index}, but there are only {amount}!")]
+    IndexOutOfRange { section: String, index: u32, amount: usize },
+
+    /// Error called when a section doesn't start with the magic it should
+    #[error("Expected {expected} at offset {offset:#x}, found {found}!")]
+    UnexpectedMagic { expected: String, found: String, offset: u64 },
+
+    /// Error called when a section, or a string in it, ends before the data it announces.
+    /// Offsets in strings start at the string, others at the file.
+    #[error("{section} is cut short at offset {offset:#x}!")]
+    Truncated { section: String, offset: u64 },
+
+    /// Error called when a section points outside of itself
+    #[error("{section} points outside of itself, to offset {offset:#x}!")]
+    OffsetOutOfRange { section: String, offset: u64 },
+
+    /// Error called when a label refers to a string TXT2 doesn't have
+    #[error("Label {label} refers to string {index}, but TXT2 only has {amount}!")]
+    LabelIndexOutOfRange { label: String, index: u32, amount: usize },
+
+    /// Error called when a tag in a string ends before its parameters do.
+    /// The offset starts at the string.
+    #[error("Tag at offset {offset:#x} of the string is cut short!")]
+    TruncatedControlCode { offset: u64 },
+
+    /// Error called when a named tag isn't in the project or profile
+    #[error("Unknown tag {name} at column {column}!")]
+    UnknownTagName { name: String, column: usize },
+
+    /// Error called when a tag's markup can't be read, i.e. a RawCmd with bad hex
+    #[error("Tag {tag} at column {column} is malformed!")]
+    MalformedTag { tag: String, column: usize },
+
+    /// Error called when a named tag's parameters aren't written name=value
+    #[error("Parameters of tag {tag} can't be read!")]
+    MalformedParameters { tag: String },
+
+    /// Error called when a named tag doesn't give one of its parameters
+    #[error("Tag {tag} needs a value for {parameter}!")]
+    MissingParameter { tag: String, parameter: String },
+
+    /// Error called when a named tag gives a parameter the project doesn't define
+    #[error("Tag {tag} has no parameter named {parameter}!")]
+    UnknownParameter { tag: String, parameter: String },
+
+    /// Error called when a message doesn't give one of the attributes the project defines
+    #[error("Attribute {0} has no value!")]
+    MissingAttribute(String),
+
+    /// Error called when a parameter or attribute value doesn't fit its type, i.e. 300 for a u8
+    #[error("{value} isn't a valid value for {name}!")]
+    InvalidValue { name: String, value: String },
+
+    /// Error called when raw bytes in a document aren't written like 01_00_FF
+    #[error("{0} isn't valid hex! Bytes are written like 01_00_FF.")]
+    InvalidHex(String),
+
+    /// Error called when an unknown section in a document doesn't have a 4 byte magic
+    #[error("Section magic {0} isn't 4 bytes long!")]
+    InvalidSectionMagic(String),
+
+    /// Error in a tag, with the column it starts at
+    #[error("At column {column}: {source}")]
+    AtColumn { column: usize, source: Box<Error> },
+
+    /// Error in one of the messages, with its label
+    #[error("In message {label}: {source}")]
+    InMessage { label: String, source: Box<Error> },
+
     /// Error called when an escape code is malformed
     #[error("Escape code is broken!")]
     MalformedEscape,
 
+    /// Error called when a UTF-16 string has a high surrogate without a low one, or the other way around
+    #[error("Unpaired surrogate {0:#06X} in string!")]
+    UnpairedSurrogate(u16),
+
+    /// Error called when a string contains a value that isn't a valid character
+    #[error("Invalid character {0:#X} in string!")]
+    InvalidCharacter(u32),
+
+    /// Error called when a compressed file is read or written without the compression feature
+    #[error("File is compressed, but msbt was built without the compression feature!")]
+    CompressionDisabled,
+
     /// Error called when a toml file is malformed
-    #[error("Toml file is malformed!!")]
+    #[error("Toml file is malformed: {0}")]
     MalformedToml(toml::de::Error),
 
+    /// Error called when a json file is malformed
+    #[cfg(feature = "json")]
+    #[error("Json file is malformed: {0}")]
+    MalformedJson(serde_json::Error),
+
+    /// Error called when a yaml file is malformed
+    #[cfg(feature = "yaml")]
+    #[error("Yaml file is malformed: {0}")]
+    MalformedYaml(serde_yaml::Error),
+
+    /// Error called when a JSON or YAML file is read or written without the feature for it
+    #[error("Reading and writing {0} files needs msbt to be built with the {0} feature!")]
+    FormatDisabled(&'static str),
+
+    /// Error called when a document can't be written in the format asked for
+    #[error("Couldn't write the file: {0}")]
+    Serialization(String),
+
+    /// Error called when a document was written by a newer version of msbtool
+    #[error("File is version {0}, but only versions up to {1} can be read!")]
+    UnsupportedDocumentVersion(u32, u32),
+
     /// Error called when the MSBT passed in argument doesn't match the one in the diff
     #[error("The hashes of the MSBT file and the diff file don't match!")]
     BadHash,
@@ -38,6 +202,26 @@ pub enum Error {
 
 }
 
+impl Error {
+    pub fn unexpected_magic(expected: &[u8], found: &[u8], offset: u64) -> Error {
+        Error::UnexpectedMagic {
+            expected: String::from_utf8_lossy(expected).into_owned(),
+            found: String::from_utf8_lossy(found).into_owned(),
+            offset
+        }
+    }
+
+    /// Tells which column of a string the error happened at.
+    pub fn at_column(self, column: usize) -> Error {
+        Error::AtColumn { column, source: Box::new(self) }
+    }
+
+    /// Tells which message the error happened in.
+    pub fn in_message(self, label: &str) -> Error {
+        Error::InMessage { label: label.to_owned(), source: Box::new(self) }
+    }
+}
+
 impl From<std::io::Error> for Error {
     fn from(err: std::io::Error) -> Self {
         Self::IOError(err)
@@ -54,4 +238,17 @@ impl From<toml::de::Error> for Error {
     fn from(err: toml::de::Error) -> Self {
         Self::MalformedToml(err)
     }
-}
\ No newline at end of file
+}
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Self::MalformedJson(err)
+    }
+}
+
+#[cfg(feature = "yaml")]
+impl From<serde_yaml::Error> for Error {
+    fn from(err: serde_yaml::Error) -> Self {
+        Self::MalformedYaml(err)
+    }
+}
@@ -1,6 +1,13 @@
 pub mod structs;
 pub mod error;
 pub mod msbt;
+pub mod msbp;
+pub mod profile;
 pub mod control_codes;
+pub mod message;
+pub mod glyphs;
+pub mod compression;
+pub mod sarc;
+pub mod document;
 
 pub use error::{Error, Result};
\ No newline at end of file
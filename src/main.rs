@@ -1,13 +1,20 @@
-use std::collections::HashMap;
 use std::fs::{self, File};
-use std::io::{self, BufRead, Read, Write};
-use std::path::Path;
+use std::io::{self, BufRead, BufWriter, Cursor, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::ExitCode;
 
 use diff_utils::convert_diff;
 use ::msbt::msbt::MSBTString;
 use clap::{Parser, ValueEnum};
 use msbt::msbt;
-use serde::{Deserialize, Serialize};
+use ::msbt::structs::Encoding;
+use ::msbt::compression::{self, Compression};
+use ::msbt::sarc;
+use ::msbt::msbp::{self, MSBP};
+use ::msbt::profile;
+use ::msbt::glyphs::{Glyphs, Platform};
+use ::msbt::document::{Document, Format};
+use log::info;
 
 mod diff_utils;
 
@@ -21,6 +28,38 @@ struct Args {
     /// Specify an output file.
     output: Option<String>,
 
+    #[arg(long, num_args(1), required(false))]
+    /// Amount of LBL1 hash buckets to write, instead of the original file's.
+    slots: Option<u32>,
+
+    #[arg(long, num_args(1), required(false))]
+    /// MSBP project file, to write tags and attributes with their names.
+    project: Option<String>,
+
+    #[arg(long, num_args(1), required(false))]
+    /// TOML tag profile, to write a game's tags with their names. Added to the project's tags if both are given.
+    profile: Option<String>,
+
+    #[arg(long, num_args(1), required(false))]
+    /// Platform whose icons are used for escape codes: DS, 3DS, Wii or Switch.
+    platform: Option<Platform>,
+
+    #[arg(long, num_args(1), required(false))]
+    /// TOML glyph table, naming the icons of a game's own font.
+    glyphs: Option<String>,
+
+    #[arg(long, num_args(1), required(false))]
+    /// Format of extracted files: TOML, JSON or YAML. Guessed from the file name if not given, TOML otherwise.
+    format: Option<Format>,
+
+    #[arg(short, long, conflicts_with("quiet"))]
+    /// Show every step of reading and writing files.
+    verbose: bool,
+
+    #[arg(short, long)]
+    /// Only show errors.
+    quiet: bool,
+
     /// File to extract, or to use as a base for diffing.
     original: String,
 
@@ -29,66 +68,83 @@ struct Args {
 }
 #[derive(ValueEnum, Clone, Debug)]
 enum Actions {
-    /// Converts an MSBT to TOML.
+    /// Converts an MSBT to TOML, JSON or YAML.
     Extract,
-    /// Converts a TOML to MSBT.
+    /// Converts a TOML, JSON or YAML file to MSBT.
     Create,
     /// Creates a diff file between <ORIGINAL> and all the files in [EDITED].
     Diff,
-    /// Applies a diff file to an MSBT or TOML file.
+    /// Applies a diff file to an MSBT, an archive, or an extracted file.
     Patch,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-struct SerMsbt {
-    is_big_endian: bool,
-    has_attributes: bool,
-    strings: HashMap<String, String>,
+// Messages go to stderr, so they stay out of piped output
+struct Logger;
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match record.level() {
+            log::Level::Warn => eprintln!("Warning: {}", record.args()),
+            _ => eprintln!("{}", record.args()),
+        }
+    }
+
+    fn flush(&self) {}
 }
 
-fn main() -> ::msbt::Result<()> {
+static LOGGER: Logger = Logger;
+
+// Errors are printed with their message, not their debug form, so they point at what to fix
+fn main() -> ExitCode {
     let args = Args::parse();
-    match args.action {
+    let level = if args.verbose {
+        log::LevelFilter::Debug
+    } else if args.quiet {
+        log::LevelFilter::Error
+    } else {
+        log::LevelFilter::Info
+    };
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+    let result = match args.action {
         Actions::Extract => extract_msbt(args),
         Actions::Create => create_msbt_args(args),
         Actions::Diff => diff_msbt(args),
         Actions::Patch => patch_msbt(args),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            ExitCode::FAILURE
+        }
     }
 }
 
 fn extract_msbt(args: Args) -> ::msbt::Result<()> {
     let arg_filename = args.original.clone();
     let path = Path::new(&arg_filename);
-    let extension = path.extension().unwrap().to_str().unwrap().to_lowercase();
-    if extension == "msbt" {
-        let filename = path.file_stem().unwrap().to_str().unwrap();
+    if is_sarc(path)? {
+        extract_sarc(args)
+    } else if is_msbt(path) {
+        let filename = &msbt_stem(path);
         let filepath = path.parent().unwrap();
+        let project = get_project(args.project.as_deref(), args.profile.as_deref())?;
+        let glyphs = get_glyphs(args.platform, args.glyphs.as_deref())?;
+        let format = get_format(args.format, args.output.as_deref());
         let mut file = File::open(args.original)?;
-
-        let mut output_map = HashMap::new();
         let msbt = msbt::from_binary(&mut file)?;
-        let strings = msbt::get_strings(msbt.clone())?;
-        for string in strings {
-            let mut parsed_string =
-                ::msbt::structs::TXT2::parse_binary(string.string, msbt.endianness);
-            if parsed_string.ends_with('\0'){
-                parsed_string.truncate(parsed_string.len() - 1);
-            }
-            output_map.insert(string.label, parsed_string);
-        }
-        let order = match msbt.endianness {
-            bytestream::ByteOrder::BigEndian => true,
-            bytestream::ByteOrder::LittleEndian => false,
-        };
-        let msbt_json = SerMsbt {
-            is_big_endian: order,
-            has_attributes: msbt.has_attributes,
-            strings: output_map,
-        };
-        let serialized = toml::ser::to_string_pretty(&msbt_json).unwrap();
-        
-        
-        let output_path = if args.output.is_some(){args.output.unwrap()} else {filepath.join(filename.to_owned() + ".toml").into_os_string().into_string().unwrap()};
+        let serialized = Document::from_msbt(&msbt, project.as_ref(), &glyphs)?.to_string(format)?;
+
+        let output_path = if let Some(output) = args.output {output} else {filepath.join(filename.to_owned() + "." + format.extension()).into_os_string().into_string().unwrap()};
         let mut result = File::create(output_path)?;
         result.write_all(serialized.as_bytes())?;
         Ok(())
@@ -97,24 +153,69 @@ fn extract_msbt(args: Args) -> ::msbt::Result<()> {
     }
 }
 
+// Every MSBT in the archive goes to <output>/<path in the archive>.toml, or the extension of the format
+fn extract_sarc(args: Args) -> ::msbt::Result<()> {
+    let arg_filename = args.original.clone();
+    let path = Path::new(&arg_filename);
+    let filename = &archive_stem(path);
+    let filepath = path.parent().unwrap();
+    let project = get_project(args.project.as_deref(), args.profile.as_deref())?;
+    let glyphs = get_glyphs(args.platform, args.glyphs.as_deref())?;
+    let format = get_format(args.format, None);
+    let mut file = File::open(args.original)?;
+    let sarc = sarc::from_binary(&mut file)?;
+    let output_dir = if let Some(output) = args.output {Path::new(&output).to_path_buf()} else {filepath.join(filename)};
+    // Checked before anything is written
+    let names = sarc.msbt_files().into_iter().map(|name| archive_relative_path(&name).map(|path| (name, path))).collect::<::msbt::Result<Vec<_>>>()?;
+    for (name, relative_path) in names {
+        info!("Extracting {}...", name);
+        let msbt = msbt::from_binary(&mut Cursor::new(&sarc.get_file(&name).unwrap().data))?;
+        let serialized = Document::from_msbt(&msbt, project.as_ref(), &glyphs)?.to_string(format)?;
+        let output_path = output_dir.join(relative_path.with_extension(format.extension()));
+        fs::create_dir_all(output_path.parent().unwrap())?;
+        let mut result = File::create(output_path)?;
+        result.write_all(serialized.as_bytes())?;
+    }
+    Ok(())
+}
+
+// Names in the archive come from the file, so they mustn't be able to point outside of the output folder
+fn archive_relative_path(name: &str) -> ::msbt::Result<PathBuf> {
+    let mut path = PathBuf::new();
+    for component in Path::new(name).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {},
+            _ => return Err(::msbt::Error::UnsafeArchivePath(name.to_owned()))
+        }
+    }
+    if path.as_os_str().is_empty() {
+        return Err(::msbt::Error::UnsafeArchivePath(name.to_owned()));
+    }
+    Ok(path)
+}
+
 fn create_msbt_args(args: Args) -> ::msbt::Result<()> {
     let arg_filename = args.original.clone();
     let path = Path::new(&arg_filename);
     let filename = path.file_stem().unwrap().to_str().unwrap();
     let filepath = path.parent().unwrap();
-    let file = File::open(args.original)?;
-    let toml = get_toml(file)?;
-    let strings = get_strings_toml(&toml)?;
-    let order = get_endianness_toml(&toml)?;
-    let output_path = if args.output.is_some(){args.output.unwrap()} else {filepath.join(filename.to_owned() + ".msbt").into_os_string().into_string().unwrap()};
-    let _ = create_msbt(output_path, strings, order);
-    Ok(())
+    let document = get_document(&args.original, args.format)?;
+    let project = get_project(args.project.as_deref(), args.profile.as_deref())?;
+    let glyphs = get_glyphs(args.platform.or(document.meta.platform), args.glyphs.as_deref())?;
+    let mut msbt = document.to_msbt(project.as_ref(), &glyphs)?;
+    if let Some(slots) = args.slots {
+        msbt::set_label_slots(&mut msbt, slots);
+    }
+    let output_path = if let Some(output) = args.output {output} else {filepath.join(filename.to_owned() + ".msbt").into_os_string().into_string().unwrap()};
+    msbt.compression = Compression::from_filename(&output_path);
+    create_msbt(output_path, &msbt)
 }
 
-fn create_msbt(filename: String, msbt: Vec<MSBTString>, order: bytestream::ByteOrder) -> ::msbt::Result<()>{
-    let new_msbt = msbt::to_binary(msbt, order)?;
-    let mut result = File::create(filename)?;
-    result.write_all(&new_msbt)?;
+fn create_msbt(filename: String, msbt: &msbt::MSBT) -> ::msbt::Result<()>{
+    let mut result = BufWriter::new(File::create(filename)?);
+    msbt.write_to(&mut result)?;
+    result.flush()?;
     Ok(())
 }
 
@@ -125,26 +226,33 @@ fn create_msbt(filename: String, msbt: Vec<MSBTString>, order: bytestream::ByteO
 fn diff_msbt(args: Args) -> ::msbt::Result<()> {
     let arg_filename = args.original.clone();
     let path = Path::new(&arg_filename);
-    let filename = path.file_stem().unwrap().to_str().unwrap();
+    if is_sarc(path)? {
+        return diff_sarc(args);
+    }
+    let filename = &msbt_stem(path);
     let filepath = path.parent().unwrap();
-    let extension = path.extension().unwrap().to_str().unwrap().to_lowercase();
+    let project = get_project(args.project.as_deref(), args.profile.as_deref())?;
+    let mut glyphs = get_glyphs(args.platform, args.glyphs.as_deref())?;
 
     //Getting original strings....
     let orig_strings;
     let hash;
     let endianness;
-    if extension == "msbt" {
+    let encoding;
+    if is_msbt(path) {
         let bytes = fs::read(args.original.clone()).unwrap();
         hash = sha256::digest(bytes);
         let mut file = File::open(args.original)?;
         let msbt = msbt::from_binary(&mut file)?;
-        endianness = msbt.endianness;
+        endianness = msbt.endianness();
+        encoding = msbt.encoding();
         orig_strings = msbt::get_strings(msbt.clone())?;
-    } else { //Just assume it's toml
-        let file = File::open(args.original)?;
-        let toml = get_toml(file)?;
-        endianness = get_endianness_toml(&toml)?;
-        orig_strings = get_strings_toml(&toml)?;
+    } else { //Just assume it's an extracted file, TOML if the extension doesn't say
+        let document = get_document(&args.original, args.format)?;
+        endianness = document.byte_order();
+        encoding = document.meta.encoding;
+        glyphs.platform = glyphs.platform.or(document.meta.platform);
+        orig_strings = document.strings(project.as_ref(), &glyphs)?;
         hash = "".to_owned();
     }
 
@@ -153,15 +261,12 @@ fn diff_msbt(args: Args) -> ::msbt::Result<()> {
     for path_edited in args.edited {
         let arg_filename = path_edited.clone();
         let path = Path::new(&arg_filename);
-        let extension = path.extension().unwrap().to_str().unwrap().to_lowercase();
-        let edited_string_single = if extension == "msbt" {
+        let edited_string_single = if is_msbt(path) {
             let mut file = File::open(path_edited)?;
             let msbt = msbt::from_binary(&mut file)?;
             msbt::get_strings(msbt.clone())?
-        } else { //Just assume it's toml
-            let file = File::open(path_edited)?;
-            let toml = get_toml(file)?;
-            get_strings_toml(&toml)?
+        } else { //Just assume it's an extracted file
+            get_document(&path_edited, args.format)?.strings(project.as_ref(), &glyphs)?
         };
         edited_strings.push(edited_string_single);
     }
@@ -171,7 +276,7 @@ fn diff_msbt(args: Args) -> ::msbt::Result<()> {
 
 
     if !added_strings.is_empty() || !deleted_strings.is_empty() || !edited_strings.is_empty(){
-        let output_path = if args.output.is_some(){args.output.unwrap()} else {filepath.join(filename.to_owned() + ".msbd.txt").into_os_string().into_string().unwrap()};
+        let output_path = if let Some(output) = args.output {output} else {filepath.join(filename.to_owned() + ".msbd.txt").into_os_string().into_string().unwrap()};
         let mut diff_file = File::create(output_path)?;
 
         //Writing file
@@ -182,38 +287,95 @@ fn diff_msbt(args: Args) -> ::msbt::Result<()> {
         }
         let _ = diff_file.write("\n".as_bytes());
 
-        //Writing added strings...
-        for string in added_strings{
-            let label = "+".to_owned()+&string.label+"\n";
-            let _ = diff_file.write(label.as_bytes());
-            let mut parsed_string = ::msbt::structs::TXT2::parse_binary(string.string, endianness);
-            if parsed_string.ends_with('\0'){
-                parsed_string.truncate(parsed_string.len() - 1);
+        write_string_diffs(&mut diff_file, (added_strings, deleted_strings, edited_strings), endianness, encoding, project.as_ref(), &glyphs)?;
+    }
+    Ok(())
+}
+
+// Same as diff_msbt, with every MSBT of the archive in its own "@path" block
+fn diff_sarc(args: Args) -> ::msbt::Result<()> {
+    let arg_filename = args.original.clone();
+    let path = Path::new(&arg_filename);
+    let filename = &archive_stem(path);
+    let filepath = path.parent().unwrap();
+    let project = get_project(args.project.as_deref(), args.profile.as_deref())?;
+    let glyphs = get_glyphs(args.platform, args.glyphs.as_deref())?;
+    let bytes = fs::read(args.original.clone())?;
+    let hash = sha256::digest(bytes);
+    let mut file = File::open(args.original)?;
+    let original = sarc::from_binary(&mut file)?;
+    let mut edited_archives = Vec::<sarc::SARC>::new();
+    for path_edited in args.edited {
+        let mut file = File::open(path_edited)?;
+        edited_archives.push(sarc::from_binary(&mut file)?);
+    }
+
+    let mut diff_file = None;
+    for name in original.msbt_files() {
+        let msbt = msbt::from_binary(&mut Cursor::new(&original.get_file(&name).unwrap().data))?;
+        let orig_strings = msbt::get_strings(msbt.clone())?;
+        let mut edited_strings = Vec::<Vec<MSBTString>>::new();
+        for archive in &edited_archives {
+            if let Some(file) = archive.get_file(&name) {
+                edited_strings.push(msbt::get_strings(msbt::from_binary(&mut Cursor::new(&file.data))?)?);
             }
-            parsed_string = parsed_string.replace('\n', "\n>");
-            let _ = diff_file.write((">".to_owned()+&parsed_string+"\n").as_bytes());
-            let _ = diff_file.write("\n".as_bytes());
         }
+        let added_strings = diff_utils::get_added(orig_strings.clone(), edited_strings.clone());
+        let deleted_strings = diff_utils::get_deleted(orig_strings.clone(), edited_strings.clone());
+        let edited_strings = diff_utils::get_edited(orig_strings, edited_strings);
+        if added_strings.is_empty() && deleted_strings.is_empty() && edited_strings.is_empty(){
+            continue;
+        }
+        if diff_file.is_none() {
+            let output_path = if let Some(output) = args.output.clone() {output} else {filepath.join(filename.to_owned() + ".msbd.txt").into_os_string().into_string().unwrap()};
+            let mut new_file = File::create(output_path)?;
+            let _ = new_file.write((filename.to_owned()+"\n").as_bytes());
+            let _ = new_file.write((filename.to_owned()+"\n").as_bytes());
+            let _ = new_file.write((hash.clone()+"\n").as_bytes());
+            let _ = new_file.write("\n".as_bytes());
+            diff_file = Some(new_file);
+        }
+        let diff_file = diff_file.as_mut().unwrap();
+        let _ = diff_file.write(("@".to_owned()+&name+"\n").as_bytes());
+        write_string_diffs(diff_file, (added_strings, deleted_strings, edited_strings), msbt.endianness(), msbt.encoding(), project.as_ref(), &glyphs)?;
+    }
+    Ok(())
+}
 
-        //Writing deleted strings...
-        for string in deleted_strings{
-            let label = "-".to_owned()+&string.label+"\n";
-            let _ = diff_file.write(label.as_bytes());
-            let _ = diff_file.write("\n".as_bytes());
+// Changes are (added, deleted, edited) strings
+fn write_string_diffs(diff_file: &mut File, changes: (Vec<MSBTString>, Vec<MSBTString>, Vec<MSBTString>), endianness: bytestream::ByteOrder, encoding: Encoding, project: Option<&MSBP>, glyphs: &Glyphs) -> ::msbt::Result<()> {
+    let (added_strings, deleted_strings, edited_strings) = changes;
+    //Writing added strings...
+    for string in added_strings{
+        let label = "+".to_owned()+&string.label+"\n";
+        let _ = diff_file.write(label.as_bytes());
+        let mut parsed_string = ::msbt::structs::TXT2::parse_binary(string.string, endianness, encoding, project, glyphs).map_err(|err| err.in_message(&string.label))?;
+        if parsed_string.ends_with('\0'){
+            parsed_string.truncate(parsed_string.len() - 1);
         }
+        parsed_string = parsed_string.replace('\n', "\n>");
+        let _ = diff_file.write((">".to_owned()+&parsed_string+"\n").as_bytes());
+        let _ = diff_file.write("\n".as_bytes());
+    }
 
-        //Writing edits...
-        for string in edited_strings{
-            let label = "~".to_owned()+&string.label+"\n";
-            let _ = diff_file.write(label.as_bytes());
-            let mut parsed_string = ::msbt::structs::TXT2::parse_binary(string.string, endianness);
-            if parsed_string.ends_with('\0'){
-                parsed_string.truncate(parsed_string.len() - 1);
-            }
-            parsed_string = parsed_string.replace('\n', "\n>");
-            let _ = diff_file.write((">".to_owned()+&parsed_string+"\n").as_bytes());
-            let _ = diff_file.write("\n".as_bytes());
+    //Writing deleted strings...
+    for string in deleted_strings{
+        let label = "-".to_owned()+&string.label+"\n";
+        let _ = diff_file.write(label.as_bytes());
+        let _ = diff_file.write("\n".as_bytes());
+    }
+
+    //Writing edits...
+    for string in edited_strings{
+        let label = "~".to_owned()+&string.label+"\n";
+        let _ = diff_file.write(label.as_bytes());
+        let mut parsed_string = ::msbt::structs::TXT2::parse_binary(string.string, endianness, encoding, project, glyphs).map_err(|err| err.in_message(&string.label))?;
+        if parsed_string.ends_with('\0'){
+            parsed_string.truncate(parsed_string.len() - 1);
         }
+        parsed_string = parsed_string.replace('\n', "\n>");
+        let _ = diff_file.write((">".to_owned()+&parsed_string+"\n").as_bytes());
+        let _ = diff_file.write("\n".as_bytes());
     }
     Ok(())
 }
@@ -235,55 +397,142 @@ fn patch_msbt(args: Args) -> ::msbt::Result<()> {
 
     let arg_filename = args.original.clone();
     let path = Path::new(&arg_filename);
-    let extension = path.extension().unwrap().to_str().unwrap().to_lowercase();
-    if extension == "msbt" {
-        let _filename = path.file_stem().unwrap().to_str().unwrap();
+    if is_sarc(path)? {
         let filepath = path.parent().unwrap();
+        let project = get_project(args.project.as_deref(), args.profile.as_deref())?;
+        let glyphs = get_glyphs(args.platform, args.glyphs.as_deref())?;
+        let mut file = File::open(args.original)?;
+        let mut sarc = sarc::from_binary(&mut file)?;
+        let diff = convert_diff(lines).unwrap();
+        for name in diff_utils::get_files(&diff) {
+            info!("Patching {}...", name);
+            let data = match sarc.get_file(&name) {
+                Some(file) => file.data.clone(),
+                None => return Err(::msbt::Error::MissingArchiveFile(name)),
+            };
+            let mut msbt = msbt::from_binary(&mut Cursor::new(data))?;
+            if let Some(slots) = args.slots {
+                msbt::set_label_slots(&mut msbt, slots);
+            }
+            let strings = msbt::get_strings(msbt.clone())?;
+            let new_msbt = diff_utils::patch_diff(diff_utils::get_file_diff(&diff, &name), strings, msbt.endianness(), msbt.encoding(), project.as_ref(), &glyphs)?;
+            sarc.set_file(&name, msbt::to_binary(&msbt, new_msbt)?);
+        }
+        let output_path = if let Some(output) = args.output {output} else {filepath.join(patch_name.to_owned() + &archive_extension(path)).into_os_string().into_string().unwrap()};
+        let mut result = File::create(output_path)?;
+        result.write_all(&sarc::to_binary(&sarc)?)?;
+    } else if is_msbt(path) {
+        let filepath = path.parent().unwrap();
+        let project = get_project(args.project.as_deref(), args.profile.as_deref())?;
+        let glyphs = get_glyphs(args.platform, args.glyphs.as_deref())?;
         let mut file = File::open(args.original)?;
 
-        let msbt = msbt::from_binary(&mut file)?;
+        let mut msbt = msbt::from_binary(&mut file)?;
+        if let Some(slots) = args.slots {
+            msbt::set_label_slots(&mut msbt, slots);
+        }
         let strings = msbt::get_strings(msbt.clone())?;
         
         let diff = convert_diff(lines).unwrap();
-        let new_msbt = diff_utils::patch_diff(diff, strings, msbt.endianness)?;
+        let new_msbt = diff_utils::patch_diff(diff, strings, msbt.endianness(), msbt.encoding(), project.as_ref(), &glyphs)?;
+        msbt::set_strings(&mut msbt, new_msbt);
 
-        let output_path = if args.output.is_some(){args.output.unwrap()} else {filepath.join(patch_name.to_owned() + ".msbt").into_os_string().into_string().unwrap()};
-        match create_msbt(output_path, new_msbt, msbt.endianness){
-            Ok(_) => {},
-            Err(_) => panic!("Error writing MSBT file."),
-        };
+        let output_path = if let Some(output) = args.output {output} else {filepath.join(patch_name.to_owned() + ".msbt" + msbt.compression.extension()).into_os_string().into_string().unwrap()};
+        create_msbt(output_path, &msbt)?;
+    } else if args.format.is_some() || Format::from_filename(&args.original).is_some() {
+        // Extracted files are patched through an MSBT, and written back in their own format
+        let filepath = path.parent().unwrap();
+        let format = get_format(args.format, Some(&args.original));
+        let document = get_document(&args.original, Some(format))?;
+        let project = get_project(args.project.as_deref(), args.profile.as_deref())?;
+        let glyphs = get_glyphs(args.platform.or(document.meta.platform), args.glyphs.as_deref())?;
+        let mut msbt = document.to_msbt(project.as_ref(), &glyphs)?;
+        let strings = msbt::get_strings(msbt.clone())?;
+
+        let diff = convert_diff(lines).unwrap();
+        let new_msbt = diff_utils::patch_diff(diff, strings, msbt.endianness(), msbt.encoding(), project.as_ref(), &glyphs)?;
+        msbt::set_strings(&mut msbt, new_msbt);
+        let mut patched = Document::from_msbt(&msbt, project.as_ref(), &glyphs)?;
+        // Comments aren't in the MSBT, so they come from the original
+        for message in &mut patched.messages {
+            if let Some(original) = document.messages.iter().find(|m| m.label == message.label) {
+                message.comment = original.comment.clone();
+            }
+        }
+
+        let output_path = if let Some(output) = args.output {output} else {filepath.join(patch_name.to_owned() + "." + format.extension()).into_os_string().into_string().unwrap()};
+        fs::write(output_path, patched.to_string(format)?)?;
+    } else {
+        return Err(::msbt::Error::NotMSBT);
     }
     Ok(())
 }
 
-fn get_toml(mut file: File) -> ::msbt::Result<SerMsbt>{
-    let mut toml_string = "".to_owned();
-    let _ = file.read_to_string(&mut toml_string);
-    Ok(toml::de::from_str(toml_string.as_str())?)
+// Archives are recognised by their content, as their extension varies a lot (.sarc, .pack, .szs...)
+fn is_sarc(path: &Path) -> ::msbt::Result<bool> {
+    if Format::from_filename(path.to_str().unwrap()).is_some() {
+        return Ok(false);
+    }
+    let data = fs::read(path)?;
+    let compression = Compression::detect(&data);
+    Ok(compression::decompress(&data, compression)?.starts_with(b"SARC"))
+}
+
+// File name up to the first dot, i.e. "Msg_EUen" for Msg_EUen.product.sarc.zs
+fn archive_stem(path: &Path) -> String {
+    let name = path.file_name().unwrap().to_str().unwrap();
+    name.split('.').next().unwrap().to_owned()
 }
 
-fn get_endianness_toml(toml: &SerMsbt) -> ::msbt::Result<bytestream::ByteOrder> {
-    match toml.is_big_endian {
-        true => Ok(bytestream::ByteOrder::BigEndian),
-        false => Ok(bytestream::ByteOrder::LittleEndian),
-    }
+// Everything after the stem, i.e. ".product.sarc.zs"
+fn archive_extension(path: &Path) -> String {
+    let name = path.file_name().unwrap().to_str().unwrap();
+    name[archive_stem(path).len()..].to_owned()
 }
 
-fn get_strings_toml(toml: &SerMsbt) -> ::msbt::Result<Vec<MSBTString>>{
-    let mut strings = Vec::<MSBTString>::new();
-    let order = match toml.is_big_endian {
-        true => bytestream::ByteOrder::BigEndian,
-        false => bytestream::ByteOrder::LittleEndian,
+// MSBT files can also be compressed, i.e. file.msbt.zs or file.szs
+fn is_msbt(path: &Path) -> bool {
+    let extension = path.extension().unwrap().to_str().unwrap().to_lowercase();
+    extension == "msbt" || Compression::from_filename(path.to_str().unwrap()) != Compression::None
+}
+
+// File name without the .msbt and compression extensions
+fn msbt_stem(path: &Path) -> String {
+    let name = path.file_name().unwrap().to_str().unwrap();
+    let name = &name[..name.len() - Compression::from_filename(name).extension().len()];
+    Path::new(name).file_stem().unwrap().to_str().unwrap().to_owned()
+}
+
+fn get_project(project: Option<&str>, profile: Option<&str>) -> ::msbt::Result<Option<MSBP>> {
+    let project = match project {
+        Some(project) => Some(msbp::from_binary(&mut File::open(project)?)?),
+        None => None,
     };
-    println!("Parsing {} string(s)...", toml.strings.len());
-    for (i, (label, string)) in toml.strings.iter().enumerate() {
-        let corrected_string = string.to_owned() + "\0";
-        strings.push(MSBTString {
-            index: i as u32,
-            label: label.to_string(),
-            string: ::msbt::structs::TXT2::parse_string(&corrected_string, order).unwrap(),
-        });
+    match profile {
+        Some(profile) => {
+            let profile = profile::from_toml(&fs::read_to_string(profile)?)?;
+            let mut project = project.unwrap_or_else(|| msbp::new(bytestream::ByteOrder::LittleEndian));
+            project.add_tag_groups(profile.tag_groups);
+            Ok(Some(project))
+        }
+        None => Ok(project),
     }
-    println!("Parsed {} string(s).", strings.len());
-    Ok(strings)
+}
+
+fn get_glyphs(platform: Option<Platform>, glyphs: Option<&str>) -> ::msbt::Result<Glyphs> {
+    let mut result = Glyphs::new(platform);
+    if let Some(glyphs) = glyphs {
+        result.add_toml(&fs::read_to_string(glyphs)?)?;
+    }
+    Ok(result)
+}
+
+// The format asked for, or else the one of the file name, or else TOML
+fn get_format(format: Option<Format>, filename: Option<&str>) -> Format {
+    format.or_else(|| filename.and_then(Format::from_filename)).unwrap_or_default()
+}
+
+fn get_document(filename: &str, format: Option<Format>) -> ::msbt::Result<Document> {
+    let string = fs::read_to_string(filename)?;
+    Document::from_str(&string, get_format(format, Some(filename)))
 }
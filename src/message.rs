@@ -0,0 +1,141 @@
+use std::collections::VecDeque;
+
+use crate::control_codes::{read_control_code, read_control_code_close, write_control_code, write_control_code_binary, write_control_code_close, write_control_code_close_binary};
+use crate::error::{Error, Result};
+use crate::msbp::MSBP;
+use crate::structs::Encoding;
+use crate::glyphs::{is_private_use, Glyphs};
+use crate::structs::txt2::{convert_char, read_char, TXT2};
+
+/// A message from TXT2, split into text, tags and glyphs.
+/// Decoding then encoding a message gives back the same bytes, terminator included.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Message{
+    pub segments: Vec<Segment>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment{
+    Text(String),
+    TagOpen {
+        group: u16,
+        tag_type: u16,
+        params: Vec<u8> // As stored in the file, in its byte order
+    },
+    TagClose {
+        group: u16,
+        tag_type: u16
+    },
+    Glyph(char) // Private use character, i.e. a button icon
+}
+
+impl Message{
+    pub fn new() -> Message {
+        Message { segments: Vec::<Segment>::new() }
+    }
+
+    /// Splits a message as stored in TXT2.
+    pub fn from_binary(string: &[u8], order: bytestream::ByteOrder, encoding: Encoding) -> Result<Message> {
+        let mut message = Message::new();
+        let mut revert_string: VecDeque<u8> = string.iter().copied().collect();
+        while !revert_string.is_empty() {
+            // Where the character or tag starts, for errors
+            let offset = (string.len() - revert_string.len()) as u64;
+            let char = read_char(&mut revert_string, order, encoding).map_err(|err| at_offset(err, offset))?;
+            if char == '\u{0E}' { //Start of control code!
+                let (group, tag_type, params) = read_control_code(&mut revert_string, order).map_err(|err| at_offset(err, offset))?;
+                message.segments.push(Segment::TagOpen { group, tag_type, params });
+            } else if char == '\u{0F}' { // End of control code!
+                let (group, tag_type) = read_control_code_close(&mut revert_string, order).map_err(|err| at_offset(err, offset))?;
+                message.segments.push(Segment::TagClose { group, tag_type });
+            } else if is_private_use(&char) {
+                message.segments.push(Segment::Glyph(char));
+            } else {
+                message.push_text(&char.to_string());
+            }
+        }
+        Ok(message)
+    }
+
+    /// Parses bracket markup, as written by extract.
+    pub fn from_markup(string: &str, order: bytestream::ByteOrder, encoding: Encoding, project: Option<&MSBP>, glyphs: &Glyphs) -> Result<Message> {
+        let binary = TXT2::parse_string(string, order, encoding, project, glyphs)?;
+        Message::from_binary(&binary, order, encoding)
+    }
+
+    pub fn to_binary(&self, order: bytestream::ByteOrder, encoding: Encoding) -> Vec<u8> {
+        let mut result = Vec::<u8>::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => {
+                    for char in text.chars() {
+                        result.append(&mut convert_char(char, order, encoding));
+                    }
+                }
+                Segment::TagOpen { group, tag_type, params } => result.append(&mut write_control_code_binary(*group, *tag_type, params, order, encoding)),
+                Segment::TagClose { group, tag_type } => result.append(&mut write_control_code_close_binary(*group, *tag_type, order, encoding)),
+                Segment::Glyph(char) => result.append(&mut convert_char(*char, order, encoding)),
+            }
+        }
+        result
+    }
+
+    /// Writes the message as bracket markup. With a project, its tags are written with their names.
+    pub fn to_markup(&self, order: bytestream::ByteOrder, encoding: Encoding, project: Option<&MSBP>, glyphs: &Glyphs) -> String {
+        let mut result = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => result.push_str(&escape_text(text)),
+                Segment::TagOpen { group, tag_type, params } => result.push_str(&write_control_code(*group, *tag_type, params, order, encoding, project)),
+                Segment::TagClose { group, tag_type } => result.push_str(&write_control_code_close(*group, *tag_type, order, encoding, project)),
+                Segment::Glyph(char) => match glyphs.name(*char) {
+                    Some(name) => result.push_str(&format!("[!{}]", name)),
+                    None => result.push(*char),
+                },
+            }
+        }
+        result
+    }
+
+    /// Adds text at the end, merging it with the text before if there is some.
+    pub fn push_text(&mut self, text: &str) {
+        if let Some(Segment::Text(last)) = self.segments.last_mut() {
+            last.push_str(text);
+        } else {
+            self.segments.push(Segment::Text(text.to_owned()));
+        }
+    }
+
+    /// The text alone, without tags, glyphs or the terminator.
+    pub fn plain_text(&self) -> String {
+        let mut result = String::new();
+        for segment in &self.segments {
+            if let Segment::Text(text) = segment {
+                result.push_str(text);
+            }
+        }
+        result.trim_end_matches('\0').to_owned()
+    }
+
+    /// Every opening tag, as (group, type, params).
+    pub fn tags(&self) -> Vec<(u16, u16, &[u8])> {
+        self.segments.iter().filter_map(|segment| match segment {
+            Segment::TagOpen { group, tag_type, params } => Some((*group, *tag_type, params.as_slice())),
+            _ => None,
+        }).collect()
+    }
+}
+
+// Strings don't know where they start, so a truncation is moved to `offset` in the message
+fn at_offset(err: Error, offset: u64) -> Error {
+    match err {
+        Error::Truncated { section, .. } => Error::Truncated { section, offset },
+        Error::TruncatedControlCode { .. } => Error::TruncatedControlCode { offset },
+        err => err,
+    }
+}
+
+/// Escapes the brackets and backslashes of plain text, so they aren't read back as markup.
+pub fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('[', "\\[")
+}
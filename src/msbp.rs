@@ -0,0 +1,878 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{Read, Seek, SeekFrom};
+
+use bytestream::{ByteOrder, StreamReader};
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::structs::{Encoding, LBL1};
+use crate::structs::lbl1::Label;
+use crate::structs::txt2::{convert_char, read_char};
+
+/// A message project (MsgPrjBn), which names the colours, attributes, tags and styles
+/// used by the MSBT files of a game.
+#[derive(Clone)]
+pub struct MSBP{
+    pub endianness: bytestream::ByteOrder,
+    pub colours: Vec<Colour>,
+    pub attributes: Vec<AttributeInfo>, // Fields of an ATR1 record
+    pub tag_groups: Vec<TagGroup>,
+    pub styles: Vec<Style>,
+    pub source_files: Vec<String> // The project's MSBT sources, as paths
+}
+
+#[derive(Debug, Clone)]
+pub struct Colour{
+    pub name: String,
+    pub rgba: [u8;4]
+}
+
+#[derive(Debug, Clone)]
+pub struct AttributeInfo{
+    pub name: String,
+    pub kind: ParamType,
+    pub offset: u32, // Position of the field in the ATR1 record
+    pub items: Vec<String> // Possible values, for lists
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagGroup{
+    pub id: u16,
+    pub name: String,
+    #[serde(rename = "tag", default)]
+    pub tags: Vec<Tag>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tag{
+    #[serde(rename = "type")]
+    pub tag_type: u16,
+    pub name: String,
+    #[serde(default)]
+    pub params: Vec<TagParam>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagParam{
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: ParamType,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub items: Vec<String> // Possible values, for lists
+}
+
+#[derive(Debug, Clone)]
+pub struct Style{
+    pub name: String,
+    pub region_width: u32,
+    pub line_number: u32,
+    pub font_index: u32,
+    pub base_colour_index: u32
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamType{
+    U8,
+    U16,
+    U32,
+    I8,
+    I16,
+    I32,
+    F32,
+    F64,
+    String, // u16 size then text in tags, u32 offset to the ATR1 string table in attributes
+    List // u8 index in the item list
+}
+
+impl ParamType{
+    pub fn from_byte(byte: u8) -> Result<ParamType> {
+        match byte {
+            0 => Ok(ParamType::U8),
+            1 => Ok(ParamType::U16),
+            2 => Ok(ParamType::U32),
+            3 => Ok(ParamType::I8),
+            4 => Ok(ParamType::I16),
+            5 => Ok(ParamType::I32),
+            6 => Ok(ParamType::F32),
+            7 => Ok(ParamType::F64),
+            8 => Ok(ParamType::String),
+            9 => Ok(ParamType::List),
+            _ => Err(Error::UnknownParameterType(byte))
+        }
+    }
+
+    /// Size of the value in an attribute record.
+    pub fn size(self) -> u32 {
+        match self {
+            ParamType::U8 | ParamType::I8 | ParamType::List => 1,
+            ParamType::U16 | ParamType::I16 => 2,
+            ParamType::U32 | ParamType::I32 | ParamType::F32 | ParamType::String => 4,
+            ParamType::F64 => 8,
+        }
+    }
+}
+
+/// Creates an empty project, to be filled with tags from a profile.
+pub fn new(order: bytestream::ByteOrder) -> MSBP {
+    MSBP {
+        endianness: order,
+        colours: Vec::<Colour>::new(),
+        attributes: Vec::<AttributeInfo>::new(),
+        tag_groups: Vec::<TagGroup>::new(),
+        styles: Vec::<Style>::new(),
+        source_files: Vec::<String>::new()
+    }
+}
+
+pub fn from_binary<R: Read+Seek>(buffer: &mut R) -> Result<MSBP> {
+    debug!("Extracting project...");
+    let mut magic = vec![0u8;8];
+    buffer.read_exact(&mut magic)?;
+    if magic != b"MsgPrjBn" {
+        return Err(Error::NotMSBP)
+    }
+    let order = match u16::read_from(buffer, ByteOrder::BigEndian)? {
+        0xFEFF => ByteOrder::BigEndian,
+        _ => ByteOrder::LittleEndian,
+    };
+    buffer.seek(SeekFrom::Start(0x0E))?;
+    let section_amount = u16::read_from(buffer, order)?;
+    buffer.seek(SeekFrom::Start(0x20))?;
+
+    let mut colours = Vec::<[u8;4]>::new();
+    let mut colour_labels = Vec::<Label>::new();
+    let mut attribute_infos = Vec::<(ParamType, u16, u32)>::new(); // (type, list index, offset)
+    let mut attribute_labels = Vec::<Label>::new();
+    let mut lists = Vec::<Vec<String>>::new();
+    let mut groups = Vec::<(u16, Vec<u16>, String)>::new(); // (id, tag indices, name)
+    let mut tags = Vec::<(Vec<u16>, String)>::new(); // (parameter indices, name)
+    let mut params = Vec::<(ParamType, Vec<u16>, String)>::new(); // (type, item indices, name)
+    let mut items = Vec::<String>::new();
+    let mut styles = Vec::<[u32;4]>::new();
+    let mut style_labels = Vec::<Label>::new();
+    let mut source_files = Vec::<String>::new();
+    let mut section_magic = [0u8;4];
+    for _i in 0..section_amount {
+        let block_start = buffer.stream_position()?;
+        buffer.read_exact(&mut section_magic)?;
+        let section_size = u32::read_from(buffer, order)?;
+        buffer.seek(SeekFrom::Current(8))?;
+        let data_start = buffer.stream_position()?;
+        match &section_magic {
+            b"CLR1" => {
+                for _i in 0..u32::read_from(buffer, order)? {
+                    let mut rgba = [0u8;4];
+                    buffer.read_exact(&mut rgba)?;
+                    colours.push(rgba);
+                }
+            }
+            b"CLB1" => colour_labels = LBL1::read_table(buffer, order)?,
+            b"ATI2" => {
+                for _i in 0..u32::read_from(buffer, order)? {
+                    let kind = ParamType::from_byte(u8::read_from(buffer, order)?)?;
+                    buffer.seek(SeekFrom::Current(1))?;
+                    let list_index = u16::read_from(buffer, order)?;
+                    let offset = u32::read_from(buffer, order)?;
+                    attribute_infos.push((kind, list_index, offset));
+                }
+            }
+            b"ALB1" => attribute_labels = LBL1::read_table(buffer, order)?,
+            b"ALI2" => {
+                for list_start in read_offsets(buffer, order, data_start, true)? {
+                    let mut list = Vec::<String>::new();
+                    for item in read_offsets(buffer, order, list_start, true)? {
+                        buffer.seek(SeekFrom::Start(item))?;
+                        list.push(read_name(buffer, order)?);
+                    }
+                    lists.push(list);
+                }
+            }
+            b"TGG2" => {
+                for group in read_offsets(buffer, order, data_start, false)? {
+                    buffer.seek(SeekFrom::Start(group))?;
+                    let id = u16::read_from(buffer, order)?;
+                    let indices = read_indices(buffer, order)?;
+                    groups.push((id, indices, read_name(buffer, order)?));
+                }
+            }
+            b"TAG2" => {
+                for tag in read_offsets(buffer, order, data_start, false)? {
+                    buffer.seek(SeekFrom::Start(tag))?;
+                    let indices = read_indices(buffer, order)?;
+                    tags.push((indices, read_name(buffer, order)?));
+                }
+            }
+            b"TGP2" => {
+                for param in read_offsets(buffer, order, data_start, false)? {
+                    buffer.seek(SeekFrom::Start(param))?;
+                    let kind = ParamType::from_byte(u8::read_from(buffer, order)?)?;
+                    let mut indices = Vec::<u16>::new();
+                    if kind == ParamType::List {
+                        buffer.seek(SeekFrom::Current(1))?;
+                        indices = read_indices(buffer, order)?;
+                    }
+                    params.push((kind, indices, read_name(buffer, order)?));
+                }
+            }
+            b"TGL2" => {
+                for item in read_offsets(buffer, order, data_start, false)? {
+                    buffer.seek(SeekFrom::Start(item))?;
+                    items.push(read_name(buffer, order)?);
+                }
+            }
+            b"SYL3" => {
+                for _i in 0..u32::read_from(buffer, order)? {
+                    styles.push([
+                        u32::read_from(buffer, order)?,
+                        u32::read_from(buffer, order)?,
+                        u32::read_from(buffer, order)?,
+                        u32::read_from(buffer, order)?,
+                    ]);
+                }
+            }
+            b"SLB1" => style_labels = LBL1::read_table(buffer, order)?,
+            b"CTI1" => {
+                for file in read_offsets(buffer, order, data_start, true)? {
+                    buffer.seek(SeekFrom::Start(file))?;
+                    source_files.push(read_name(buffer, order)?);
+                }
+            }
+            _ => debug!("Skipping project section {}...", String::from_utf8_lossy(&section_magic)),
+        }
+        let leftover_padding = if section_size%0x10 != 0 {0x10-(section_size%0x10)} else {0};
+        buffer.seek(SeekFrom::Start(block_start+0x10+section_size as u64+(leftover_padding) as u64))?;
+    }
+
+    //Now that everything is read, we can put the indices back together
+    let colour_names = label_names(&colour_labels, colours.len());
+    let attribute_names = label_names(&attribute_labels, attribute_infos.len());
+    let style_names = label_names(&style_labels, styles.len());
+    let mut tag_groups = Vec::<TagGroup>::new();
+    for (id, tag_indices, name) in groups {
+        let mut group_tags = Vec::<Tag>::new();
+        for (tag_type, tag_index) in tag_indices.into_iter().enumerate() {
+            let (param_indices, tag_name) = tags.get(tag_index as usize).ok_or_else(|| out_of_range("TGG2", tag_index, tags.len()))?;
+            let mut tag_params = Vec::<TagParam>::new();
+            for param_index in param_indices {
+                let (kind, item_indices, param_name) = params.get(*param_index as usize).ok_or_else(|| out_of_range("TAG2", *param_index, params.len()))?;
+                let mut param_items = Vec::<String>::new();
+                for item_index in item_indices {
+                    param_items.push(items.get(*item_index as usize).ok_or_else(|| out_of_range("TGP2", *item_index, items.len()))?.clone());
+                }
+                tag_params.push(TagParam { name: param_name.clone(), kind: *kind, items: param_items });
+            }
+            group_tags.push(Tag { tag_type: tag_type as u16, name: tag_name.clone(), params: tag_params });
+        }
+        tag_groups.push(TagGroup { id, name, tags: group_tags });
+    }
+    let mut attributes = Vec::<AttributeInfo>::new();
+    for (i, (kind, list_index, offset)) in attribute_infos.into_iter().enumerate() {
+        let items = if kind == ParamType::List {
+            lists.get(list_index as usize).ok_or_else(|| out_of_range("ATI2", list_index, lists.len()))?.clone()
+        } else {
+            Vec::<String>::new()
+        };
+        attributes.push(AttributeInfo { name: attribute_names[i].clone(), kind, offset, items });
+    }
+    debug!("Extracted project: {} tag group(s), {} attribute(s).", tag_groups.len(), attributes.len());
+    Ok(MSBP {
+        endianness: order,
+        colours: colours.into_iter().enumerate().map(|(i, rgba)| Colour { name: colour_names[i].clone(), rgba }).collect(),
+        attributes,
+        tag_groups,
+        styles: styles.into_iter().enumerate().map(|(i, style)| Style {
+            name: style_names[i].clone(),
+            region_width: style[0],
+            line_number: style[1],
+            font_index: style[2],
+            base_colour_index: style[3]
+        }).collect(),
+        source_files
+    })
+}
+
+impl MSBP {
+    /// Adds the tags of `tag_groups`, replacing the ones that have the same group and type.
+    pub fn add_tag_groups(&mut self, tag_groups: Vec<TagGroup>) {
+        for tag_group in tag_groups {
+            match self.tag_groups.iter_mut().find(|g| g.id == tag_group.id) {
+                Some(existing) => {
+                    for tag in tag_group.tags {
+                        existing.tags.retain(|t| t.tag_type != tag.tag_type);
+                        existing.tags.push(tag);
+                    }
+                    existing.tags.sort_by_key(|t| t.tag_type);
+                }
+                None => self.tag_groups.push(tag_group),
+            }
+        }
+    }
+
+    pub fn find_tag(&self, group: u16, tag_type: u16) -> Option<(&TagGroup, &Tag)> {
+        let group = self.tag_groups.iter().find(|g| g.id == group)?;
+        Some((group, group.tags.iter().find(|t| t.tag_type == tag_type)?))
+    }
+
+    /// Name a tag is written with: its own if no other group has a tag with the same name, "Group:Tag" otherwise.
+    pub fn tag_name(&self, group: u16, tag_type: u16) -> Option<String> {
+        let (tag_group, tag) = self.find_tag(group, tag_type)?;
+        let homonyms = self.tag_groups.iter().flat_map(|g| g.tags.iter()).filter(|t| t.name == tag.name).count();
+        if homonyms > 1 {
+            Some(format!("{}:{}", tag_group.name, tag.name))
+        } else {
+            Some(tag.name.clone())
+        }
+    }
+
+    /// Finds a tag by the name `tag_name` gives it, returning its group and type.
+    pub fn find_tag_by_name(&self, name: &str) -> Option<(u16, u16, &Tag)> {
+        let (group_name, tag_name) = match name.split_once(':') {
+            Some((group_name, tag_name)) => (Some(group_name), tag_name),
+            None => (None, name),
+        };
+        for group in &self.tag_groups {
+            if group_name.is_some_and(|g| g != group.name) {
+                continue;
+            }
+            if let Some(tag) = group.tags.iter().find(|t| t.name == tag_name) {
+                return Some((group.id, tag.tag_type, tag));
+            }
+        }
+        None
+    }
+
+    /// Writes a tag with its real name and parameters, i.e. [Color name=Red]. Returns None if
+    /// the project doesn't know the tag, or if the parameters wouldn't come back identical.
+    pub fn decode_tag(&self, group: u16, tag_type: u16, params: &[u8], order: bytestream::ByteOrder, encoding: Encoding) -> Option<String> {
+        let (_, tag) = self.find_tag(group, tag_type)?;
+        let name = self.tag_name(group, tag_type)?;
+        if !name.split(':').all(is_identifier) {
+            return None;
+        }
+        let mut result = format!("[{}", name);
+        let mut pos = 0;
+        for param in &tag.params {
+            if !is_identifier(&param.name) {
+                return None;
+            }
+            let value = read_value(param.kind, &param.items, params, &mut pos, order, encoding)?;
+            result += &format!(" {}={}", param.name, value);
+        }
+        if pos != params.len() {
+            return None;
+        }
+        result += "]";
+        // Only keep the named form if it encodes back to the same bytes
+        match self.encode_params(tag, &result, order, encoding) {
+            Ok(encoded) if encoded == params => Some(result),
+            _ => None,
+        }
+    }
+
+    /// Writes a closing tag with the name of its opening tag, i.e. [/Color].
+    pub fn decode_tag_close(&self, group: u16, tag_type: u16) -> Option<String> {
+        let name = self.tag_name(group, tag_type)?;
+        if !name.split(':').all(is_identifier) {
+            return None;
+        }
+        Some(format!("[/{}]", name))
+    }
+
+    /// Converts a named tag back to binary, from the opening character to its last parameter.
+    /// Returns None if the name isn't one of the project's tags.
+    pub fn encode_tag(&self, code: &str, order: bytestream::ByteOrder, encoding: Encoding) -> Result<Option<Vec<u8>>> {
+        let bare_code = code.trim_start_matches('[').trim_end_matches(']');
+        let name = bare_code.split(' ').next().unwrap_or_default();
+        let (group, tag_type, tag) = match self.find_tag_by_name(name) {
+            Some(tag) => tag,
+            None => return Ok(None),
+        };
+        let mut params = self.encode_params(tag, code, order, encoding)?;
+        let mut raw_bytes = convert_char('\u{0E}', order, encoding);
+        raw_bytes.append(&mut ordered(group.to_be_bytes().to_vec(), order));
+        raw_bytes.append(&mut ordered(tag_type.to_be_bytes().to_vec(), order));
+        raw_bytes.append(&mut ordered((params.len() as u16).to_be_bytes().to_vec(), order));
+        raw_bytes.append(&mut params);
+        Ok(Some(raw_bytes))
+    }
+
+    fn encode_params(&self, tag: &Tag, code: &str, order: bytestream::ByteOrder, encoding: Encoding) -> Result<Vec<u8>> {
+        let bare_code = code.trim_start_matches('[').trim_end_matches(']');
+        let args = bare_code.split_once(' ').map(|(_, args)| args).unwrap_or_default();
+        let values = split_params(args).ok_or_else(|| Error::MalformedParameters { tag: tag.name.clone() })?;
+        let mut result = Vec::<u8>::new();
+        for param in &tag.params {
+            let value = values.get(&param.name).ok_or_else(|| Error::MissingParameter { tag: tag.name.clone(), parameter: param.name.clone() })?;
+            result.append(&mut write_value(param.kind, &param.items, &param.name, value, order, encoding)?);
+        }
+        if let Some(name) = values.keys().find(|name| !tag.params.iter().any(|p| &p.name == *name)) {
+            return Err(Error::UnknownParameter { tag: tag.name.clone(), parameter: name.clone() });
+        }
+        Ok(result)
+    }
+
+    /// Size of an ATR1 record, as described by the project.
+    pub fn attribute_size(&self) -> u32 {
+        self.attributes.iter().map(|a| a.offset + a.kind.size()).max().unwrap_or(0)
+    }
+
+    /// Splits an ATR1 record in named fields. String offsets are relative to the start of the ATR1
+    /// data, so `table_start` tells where `string_table` is in there. Returns None if the record
+    /// doesn't match the project.
+    pub fn decode_attributes(&self, record: &[u8], string_table: &[u8], table_start: u32, order: bytestream::ByteOrder, encoding: Encoding) -> Option<BTreeMap<String, String>> {
+        let mut fields = BTreeMap::<String, String>::new();
+        for attribute in &self.attributes {
+            let mut pos = attribute.offset as usize;
+            let value = if attribute.kind == ParamType::String {
+                let offset = u32::from_be_bytes(read_bytes::<4>(record, &mut pos, order)?);
+                let mut string = string_table.get(offset.checked_sub(table_start)? as usize..)?.iter().copied().collect::<VecDeque<u8>>();
+                let mut value = String::new();
+                loop {
+                    if string.is_empty() {
+                        return None;
+                    }
+                    match read_char(&mut string, order, encoding).ok()? {
+                        '\0' => break,
+                        char => value.push(char),
+                    }
+                }
+                value
+            } else {
+                let value = read_value(attribute.kind, &attribute.items, record, &mut pos, order, encoding)?;
+                value.trim_matches('"').to_owned()
+            };
+            fields.insert(attribute.name.clone(), value);
+        }
+        let mut table = string_table.to_vec();
+        match self.encode_attributes(&fields, &mut table, table_start, order, encoding) {
+            Ok(encoded) if encoded == record && table == string_table => Some(fields),
+            _ => None,
+        }
+    }
+
+    /// Builds an ATR1 record from named fields. Strings are looked up in `string_table`, and added
+    /// at its end if they aren't there yet.
+    pub fn encode_attributes(&self, fields: &BTreeMap<String, String>, string_table: &mut Vec<u8>, table_start: u32, order: bytestream::ByteOrder, encoding: Encoding) -> Result<Vec<u8>> {
+        let mut record = vec![0u8;self.attribute_size() as usize];
+        for attribute in &self.attributes {
+            let value = fields.get(&attribute.name).ok_or_else(|| Error::MissingAttribute(attribute.name.clone()))?;
+            let bytes = if attribute.kind == ParamType::String {
+                let mut string = Vec::<u8>::new();
+                for char in value.chars().chain(['\0']) {
+                    string.append(&mut convert_char(char, order, encoding));
+                }
+                let unit = encoding.unit_size();
+                let found = (0..string_table.len()).step_by(unit).find(|i| string_table[*i..].starts_with(&string));
+                let offset = match found {
+                    Some(offset) => offset,
+                    None => {
+                        let offset = string_table.len();
+                        string_table.append(&mut string);
+                        offset
+                    }
+                };
+                ordered((table_start + offset as u32).to_be_bytes().to_vec(), order)
+            } else {
+                write_value(attribute.kind, &attribute.items, &attribute.name, value, order, encoding)?
+            };
+            let start = attribute.offset as usize;
+            record[start..start+bytes.len()].copy_from_slice(&bytes);
+        }
+        Ok(record)
+    }
+}
+
+// Offset tables, either with a u32 amount or a u16 amount and 2 bytes of padding
+// A section giving the index of an entry in the next one, i.e. TGG2 of a tag in TAG2
+fn out_of_range(section: &str, index: u16, amount: usize) -> Error {
+    Error::IndexOutOfRange { section: section.to_owned(), index: index as u32, amount }
+}
+
+fn read_offsets<R: Read+Seek>(buffer: &mut R, order: bytestream::ByteOrder, base: u64, wide: bool) -> Result<Vec<u64>> {
+    buffer.seek(SeekFrom::Start(base))?;
+    let amount = if wide {
+        u32::read_from(buffer, order)?
+    } else {
+        let amount = u16::read_from(buffer, order)? as u32;
+        buffer.seek(SeekFrom::Current(2))?;
+        amount
+    };
+    let mut offsets = Vec::<u64>::new();
+    for _i in 0..amount {
+        offsets.push(base + u32::read_from(buffer, order)? as u64);
+    }
+    Ok(offsets)
+}
+
+fn read_indices<R: Read>(buffer: &mut R, order: bytestream::ByteOrder) -> Result<Vec<u16>> {
+    let mut indices = Vec::<u16>::new();
+    for _i in 0..u16::read_from(buffer, order)? {
+        indices.push(u16::read_from(buffer, order)?);
+    }
+    Ok(indices)
+}
+
+fn read_name<R: Read>(buffer: &mut R, order: bytestream::ByteOrder) -> Result<String> {
+    let mut name = Vec::<u8>::new();
+    loop {
+        let char = u8::read_from(buffer, order)?;
+        if char == 0 {
+            break;
+        }
+        name.push(char);
+    }
+    Ok(String::from_utf8(name)?)
+}
+
+// Entries without a label are named after their index
+fn label_names(labels: &[Label], amount: usize) -> Vec<String> {
+    let mut names: Vec<String> = (0..amount).map(|i| i.to_string()).collect();
+    for label in labels {
+        if let Some(name) = names.get_mut(label.string_index as usize) {
+            name.clone_from(&label.label);
+        }
+    }
+    names
+}
+
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Parameters are written as name=value, with strings in quotes
+fn split_params(args: &str) -> Option<BTreeMap<String, String>> {
+    let mut result = BTreeMap::<String, String>::new();
+    let mut rest = args.trim_start();
+    while !rest.is_empty() {
+        let (name, value) = rest.split_once('=')?;
+        let (value, next) = if let Some(quoted) = value.strip_prefix('"') {
+            let end = quoted.find('"')?;
+            (&quoted[..end], &quoted[end+1..])
+        } else {
+            value.split_at(value.find(' ').unwrap_or(value.len()))
+        };
+        result.insert(name.to_owned(), value.to_owned());
+        rest = next.trim_start();
+    }
+    Some(result)
+}
+
+// Bytes are kept big endian here, so this turns them into the file's order
+fn ordered(mut bytes: Vec<u8>, order: bytestream::ByteOrder) -> Vec<u8> {
+    if matches!(order, ByteOrder::LittleEndian) {
+        bytes.reverse();
+    }
+    bytes
+}
+
+fn read_bytes<const N: usize>(bytes: &[u8], pos: &mut usize, order: bytestream::ByteOrder) -> Option<[u8;N]> {
+    let mut value: [u8;N] = bytes.get(*pos..*pos+N)?.try_into().ok()?;
+    if matches!(order, ByteOrder::LittleEndian) {
+        value.reverse();
+    }
+    *pos += N;
+    Some(value)
+}
+
+fn read_value(kind: ParamType, items: &[String], bytes: &[u8], pos: &mut usize, order: bytestream::ByteOrder, encoding: Encoding) -> Option<String> {
+    let value = match kind {
+        ParamType::U8 => u8::from_be_bytes(read_bytes(bytes, pos, order)?).to_string(),
+        ParamType::U16 => u16::from_be_bytes(read_bytes(bytes, pos, order)?).to_string(),
+        ParamType::U32 => u32::from_be_bytes(read_bytes(bytes, pos, order)?).to_string(),
+        ParamType::I8 => i8::from_be_bytes(read_bytes(bytes, pos, order)?).to_string(),
+        ParamType::I16 => i16::from_be_bytes(read_bytes(bytes, pos, order)?).to_string(),
+        ParamType::I32 => i32::from_be_bytes(read_bytes(bytes, pos, order)?).to_string(),
+        ParamType::F32 => f32::from_be_bytes(read_bytes(bytes, pos, order)?).to_string(),
+        ParamType::F64 => f64::from_be_bytes(read_bytes(bytes, pos, order)?).to_string(),
+        ParamType::String => {
+            let size = u16::from_be_bytes(read_bytes(bytes, pos, order)?) as usize;
+            let mut string = bytes.get(*pos..*pos+size)?.iter().copied().collect::<VecDeque<u8>>();
+            *pos += size;
+            let mut value = String::new();
+            while !string.is_empty() {
+                value.push(read_char(&mut string, order, encoding).ok()?);
+            }
+            if value.contains('"') {
+                return None;
+            }
+            format!("\"{}\"", value)
+        }
+        ParamType::List => {
+            let item = items.get(u8::from_be_bytes(read_bytes(bytes, pos, order)?) as usize)?;
+            if is_identifier(item) {item.clone()} else {format!("\"{}\"", item)}
+        }
+    };
+    Some(value)
+}
+
+fn write_value(kind: ParamType, items: &[String], name: &str, value: &str, order: bytestream::ByteOrder, encoding: Encoding) -> Result<Vec<u8>> {
+    let bytes = match kind {
+        ParamType::U8 => value.parse::<u8>().map(|v| v.to_be_bytes().to_vec()).ok(),
+        ParamType::U16 => value.parse::<u16>().map(|v| v.to_be_bytes().to_vec()).ok(),
+        ParamType::U32 => value.parse::<u32>().map(|v| v.to_be_bytes().to_vec()).ok(),
+        ParamType::I8 => value.parse::<i8>().map(|v| v.to_be_bytes().to_vec()).ok(),
+        ParamType::I16 => value.parse::<i16>().map(|v| v.to_be_bytes().to_vec()).ok(),
+        ParamType::I32 => value.parse::<i32>().map(|v| v.to_be_bytes().to_vec()).ok(),
+        ParamType::F32 => value.parse::<f32>().map(|v| v.to_be_bytes().to_vec()).ok(),
+        ParamType::F64 => value.parse::<f64>().map(|v| v.to_be_bytes().to_vec()).ok(),
+        ParamType::String => {
+            let mut string = Vec::<u8>::new();
+            for char in value.chars() {
+                string.append(&mut convert_char(char, order, encoding));
+            }
+            let mut result = ordered((string.len() as u16).to_be_bytes().to_vec(), order);
+            result.append(&mut string);
+            return Ok(result);
+        }
+        ParamType::List => {
+            let index = items.iter().position(|i| i == value).map(|i| i as u8).or(value.parse::<u8>().ok());
+            index.map(|i| vec![i])
+        }
+    };
+    Ok(ordered(bytes.ok_or_else(|| Error::InvalidValue { name: name.to_owned(), value: value.to_owned() })?, order))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use crate::sarc::{push_u16, push_u32};
+    use super::*;
+
+    // Builds a project by hand, the same way as an MSBT, padding sections with 0xAB
+    fn build(order: ByteOrder, sections: &[(&[u8], Vec<u8>)]) -> Vec<u8> {
+        let mut body = Vec::<u8>::new();
+        for (magic, data) in sections {
+            body.extend_from_slice(magic);
+            push_u32(&mut body, data.len() as u32, order);
+            body.extend_from_slice(&[0u8;8]);
+            body.extend_from_slice(data);
+            while !body.len().is_multiple_of(16) {
+                body.push(0xAB);
+            }
+        }
+        let mut file = b"MsgPrjBn".to_vec();
+        file.extend_from_slice(match order {
+            ByteOrder::BigEndian => &[0xFE, 0xFF],
+            ByteOrder::LittleEndian => &[0xFF, 0xFE],
+        });
+        push_u16(&mut file, 0, order);
+        file.extend_from_slice(&[0, 3]);
+        push_u16(&mut file, sections.len() as u16, order);
+        push_u16(&mut file, 0, order);
+        push_u32(&mut file, 0x20 + body.len() as u32, order);
+        file.extend_from_slice(&[0u8;10]);
+        file.append(&mut body);
+        file
+    }
+
+    // An amount, then offsets from the start of the table to each entry
+    fn offset_table(entries: &[Vec<u8>], wide: bool, order: ByteOrder) -> Vec<u8> {
+        let mut data = Vec::<u8>::new();
+        if wide {
+            push_u32(&mut data, entries.len() as u32, order);
+        } else {
+            push_u16(&mut data, entries.len() as u16, order);
+            push_u16(&mut data, 0, order);
+        }
+        let mut offset = data.len() as u32 + 4*entries.len() as u32;
+        for entry in entries {
+            push_u32(&mut data, offset, order);
+            offset += entry.len() as u32;
+        }
+        for entry in entries {
+            data.extend_from_slice(entry);
+        }
+        data
+    }
+
+    fn name(name: &str) -> Vec<u8> {
+        let mut bytes = name.as_bytes().to_vec();
+        bytes.push(0);
+        bytes
+    }
+
+    fn indexed(indices: &[u16], name_text: &str, order: ByteOrder) -> Vec<u8> {
+        let mut data = Vec::<u8>::new();
+        push_u16(&mut data, indices.len() as u16, order);
+        for index in indices {
+            push_u16(&mut data, *index, order);
+        }
+        data.append(&mut name(name_text));
+        data
+    }
+
+    // A single bucket, like in the MSBT tests
+    fn labels(labels: &[(&str, u32)], order: ByteOrder) -> Vec<u8> {
+        let mut data = Vec::<u8>::new();
+        push_u32(&mut data, 1, order);
+        push_u32(&mut data, labels.len() as u32, order);
+        push_u32(&mut data, 0xC, order);
+        for (label, index) in labels {
+            data.push(label.len() as u8);
+            data.extend_from_slice(label.as_bytes());
+            push_u32(&mut data, *index, order);
+        }
+        data
+    }
+
+    fn project(order: ByteOrder) -> MSBP {
+        let mut clr1 = Vec::<u8>::new();
+        push_u32(&mut clr1, 2, order);
+        clr1.extend_from_slice(&[0xFF, 0, 0, 0xFF, 0, 0, 0xFF, 0xFF]);
+        let mut ati2 = Vec::<u8>::new();
+        push_u32(&mut ati2, 3, order);
+        for (kind, list, offset) in [(8u8, 0u16, 0u32), (9, 0, 4), (0, 0, 5)] {
+            ati2.extend_from_slice(&[kind, 0]);
+            push_u16(&mut ati2, list, order);
+            push_u32(&mut ati2, offset, order);
+        }
+        let ali2 = offset_table(&[offset_table(&[name("Happy"), name("Sad")], true, order)], true, order);
+        let tgg2 = offset_table(&[
+            {let mut group = Vec::<u8>::new(); push_u16(&mut group, 0, order); group.append(&mut indexed(&[0, 1], "System", order)); group},
+            {let mut group = Vec::<u8>::new(); push_u16(&mut group, 2, order); group.append(&mut indexed(&[2], "Sound", order)); group},
+            {let mut group = Vec::<u8>::new(); push_u16(&mut group, 3, order); group.append(&mut indexed(&[3], "Other", order)); group},
+        ], false, order);
+        let tag2 = offset_table(&[indexed(&[0], "Ruby", order), indexed(&[1], "Color", order), indexed(&[2, 3], "Play", order), indexed(&[], "Color", order)], false, order);
+        let mut list_param = vec![9u8, 0];
+        list_param.append(&mut indexed(&[1, 0], "name", order));
+        let tgp2 = offset_table(&[[vec![8], name("rt")].concat(), list_param, [vec![1], name("id")].concat(), [vec![6], name("volume")].concat()], false, order);
+        let tgl2 = offset_table(&[name("Blue"), name("Red")], false, order);
+        let mut syl3 = Vec::<u8>::new();
+        push_u32(&mut syl3, 2, order);
+        for value in [100, 2, 0, 1, 300, 4, 1, 0] {
+            push_u32(&mut syl3, value, order);
+        }
+        let cti1 = offset_table(&[name("Msg/Common.msbt")], true, order);
+        let file = build(order, &[
+            (b"CLR1", clr1),
+            (b"CLB1", labels(&[("Blue", 1), ("Red", 0)], order)),
+            (b"ATI2", ati2),
+            (b"ALB1", labels(&[("Volume", 2), ("Speaker", 0), ("Mood", 1)], order)),
+            (b"ALI2", ali2),
+            (b"TGG2", tgg2),
+            (b"TAG2", tag2),
+            (b"TGP2", tgp2),
+            (b"TGL2", tgl2),
+            (b"SYL3", syl3),
+            (b"SLB1", labels(&[("Big", 1)], order)),
+            (b"CTI1", cti1),
+            (b"ZZZ9", vec![1, 2, 3]),
+        ]);
+        from_binary(&mut Cursor::new(&file)).unwrap()
+    }
+
+    #[test]
+    fn indices_are_resolved() {
+        for order in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            let project = project(order);
+            let colours: Vec<_> = project.colours.iter().map(|c| (c.name.as_str(), c.rgba)).collect();
+            assert_eq!(colours, vec![("Red", [0xFF, 0, 0, 0xFF]), ("Blue", [0, 0, 0xFF, 0xFF])]);
+            let attributes: Vec<_> = project.attributes.iter().map(|a| (a.name.as_str(), a.kind, a.offset, a.items.clone())).collect();
+            assert_eq!(attributes, vec![
+                ("Speaker", ParamType::String, 0, vec![]),
+                ("Mood", ParamType::List, 4, vec!["Happy".to_string(), "Sad".to_string()]),
+                ("Volume", ParamType::U8, 5, vec![]),
+            ]);
+            assert_eq!(project.attribute_size(), 6);
+            let groups: Vec<_> = project.tag_groups.iter().map(|g| (g.id, g.name.as_str(), g.tags.iter().map(|t| t.name.as_str()).collect::<Vec<_>>())).collect();
+            assert_eq!(groups, vec![(0, "System", vec!["Ruby", "Color"]), (2, "Sound", vec!["Play"]), (3, "Other", vec!["Color"])]);
+            let (_, color) = project.find_tag(0, 1).unwrap();
+            assert_eq!(color.params[0].name, "name");
+            assert_eq!(color.params[0].items, vec!["Red".to_string(), "Blue".to_string()]);
+            let (_, play) = project.find_tag(2, 0).unwrap();
+            assert_eq!(play.params.iter().map(|p| (p.name.as_str(), p.kind)).collect::<Vec<_>>(), vec![("id", ParamType::U16), ("volume", ParamType::F32)]);
+            let styles: Vec<_> = project.styles.iter().map(|s| (s.name.as_str(), s.region_width, s.line_number, s.font_index, s.base_colour_index)).collect();
+            assert_eq!(styles, vec![("0", 100, 2, 0, 1), ("Big", 300, 4, 1, 0)]);
+            assert_eq!(project.source_files, vec!["Msg/Common.msbt".to_string()]);
+        }
+    }
+
+    #[test]
+    fn tag_names() {
+        let project = project(ByteOrder::LittleEndian);
+        assert_eq!(project.tag_name(0, 0).unwrap(), "Ruby");
+        assert_eq!(project.tag_name(0, 1).unwrap(), "System:Color");
+        assert_eq!(project.tag_name(3, 0).unwrap(), "Other:Color");
+        assert!(project.tag_name(1, 0).is_none());
+        assert!(project.tag_name(0, 2).is_none());
+        assert_eq!(project.find_tag_by_name("Play").map(|(g, t, _)| (g, t)), Some((2, 0)));
+        assert_eq!(project.find_tag_by_name("Other:Color").map(|(g, t, _)| (g, t)), Some((3, 0)));
+        assert!(project.find_tag_by_name("Sound:Ruby").is_none());
+    }
+
+    #[test]
+    fn tags_round_trip() {
+        for order in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            let project = project(order);
+            for encoding in [Encoding::UTF8, Encoding::UTF16, Encoding::UTF32] {
+                for (code, group, tag_type) in [
+                    ("[Ruby rt=\"ふりがな\"]", 0, 0),
+                    ("[System:Color name=Blue]", 0, 1),
+                    ("[Play id=300 volume=0.5]", 2, 0),
+                    ("[Other:Color]", 3, 0),
+                ] {
+                    let encoded = project.encode_tag(code, order, encoding).unwrap().unwrap();
+                    let mut pos = encoding.unit_size();
+                    assert_eq!(u16::from_be_bytes(read_bytes(&encoded, &mut pos, order).unwrap()), group);
+                    assert_eq!(u16::from_be_bytes(read_bytes(&encoded, &mut pos, order).unwrap()), tag_type);
+                    let size = u16::from_be_bytes(read_bytes(&encoded, &mut pos, order).unwrap()) as usize;
+                    assert_eq!(encoded.len(), pos + size);
+                    assert_eq!(project.decode_tag(group, tag_type, &encoded[pos..], order, encoding).unwrap(), code);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tags_that_dont_fit() {
+        let order = ByteOrder::LittleEndian;
+        let project = project(order);
+        assert!(project.encode_tag("[Unknown a=1]", order, Encoding::UTF16).unwrap().is_none());
+        assert!(project.encode_tag("[Play id=300]", order, Encoding::UTF16).is_err());
+        assert!(project.encode_tag("[Play id=300 volume=0.5 extra=1]", order, Encoding::UTF16).is_err());
+        assert!(project.encode_tag("[Play id=70000 volume=0.5]", order, Encoding::UTF16).is_err());
+        assert!(project.encode_tag("[System:Color name=Green]", order, Encoding::UTF16).is_err());
+        // Leftover bytes, an item past the end of the list, or an unknown tag keep the raw form
+        assert!(project.decode_tag(2, 0, &[0x2C, 0x01, 0, 0, 0, 0x3F, 0xFF], order, Encoding::UTF16).is_none());
+        assert!(project.decode_tag(0, 1, &[5], order, Encoding::UTF16).is_none());
+        assert!(project.decode_tag(1, 0, &[], order, Encoding::UTF16).is_none());
+    }
+
+    #[test]
+    fn attributes_use_the_string_table() {
+        for order in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            let project = project(order);
+            for encoding in [Encoding::UTF8, Encoding::UTF16, Encoding::UTF32] {
+                let fields = |speaker: &str, mood: &str, volume: &str| BTreeMap::from([
+                    ("Speaker".to_string(), speaker.to_string()),
+                    ("Mood".to_string(), mood.to_string()),
+                    ("Volume".to_string(), volume.to_string()),
+                ]);
+                let table_start = 8 + 3*project.attribute_size();
+                let mut string_table = Vec::<u8>::new();
+                let first = project.encode_attributes(&fields("Link", "Sad", "7"), &mut string_table, table_start, order, encoding).unwrap();
+                assert_eq!(first.len(), 6);
+                assert_eq!(&first[4..], &[1, 7]);
+                let table_size = string_table.len();
+                assert_eq!(table_size, 5*encoding.unit_size());
+                // The same string is found again instead of being added twice
+                let second = project.encode_attributes(&fields("Link", "Happy", "0"), &mut string_table, table_start, order, encoding).unwrap();
+                assert_eq!(string_table.len(), table_size);
+                assert_eq!(first[..4], second[..4]);
+                let third = project.encode_attributes(&fields("Zelda", "Happy", "255"), &mut string_table, table_start, order, encoding).unwrap();
+                assert_eq!(u32::from_be_bytes(read_bytes(&third, &mut 0, order).unwrap()), table_start + table_size as u32);
+
+                for (record, expected) in [(&first, fields("Link", "Sad", "7")), (&second, fields("Link", "Happy", "0")), (&third, fields("Zelda", "Happy", "255"))] {
+                    assert_eq!(project.decode_attributes(record, &string_table, table_start, order, encoding).unwrap(), expected);
+                }
+                // Offsets before the table, past it, or strings that aren't there all keep the raw form
+                assert!(project.decode_attributes(&first, &string_table, table_start + 1, order, encoding).is_none());
+                assert!(project.decode_attributes(&third, &string_table[..table_size], table_start, order, encoding).is_none());
+                assert!(project.decode_attributes(&first[..5], &string_table, table_start, order, encoding).is_none());
+                assert!(project.encode_attributes(&fields("Link", "Angry", "7"), &mut string_table, table_start, order, encoding).is_err());
+            }
+        }
+    }
+}
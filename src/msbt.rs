@@ -1,94 +1,453 @@
-use std::io::{Read, Seek};
+use std::collections::HashMap;
+use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
 
-use bytestream::ByteOrder;
+use bytestream::{ByteOrder, StreamReader};
+use log::{debug, warn};
 
-use crate::structs::{Header, ATR1, LBL1, TXT2};
-use crate::error::Result;
+use crate::structs::{Encoding, Header, UnknownSection, ATR1, LBL1, NLI1, TSY1, TXT2};
+use crate::structs::txt2::convert_char;
+use crate::compression::{self, Compression};
+use crate::message::Message;
+use crate::error::{Error, Result};
 
 
 #[derive(Clone)]
 pub struct MSBT{
-    _header: Header,
-    lbl1: LBL1,
-    _atr1: ATR1,
-    txt2: TXT2,
-    pub endianness: bytestream::ByteOrder,
-    pub has_attributes: bool
+    header: Header,
+    sections: Vec<Section>, // In the same order as in the file
+    padding: u8, // Byte used to align sections to 16 bytes
+    pub compression: Compression, // Applied by to_binary, set by from_binary when the input was compressed
+    strings: Vec<MSBTString>, // In index order
+    labels: HashMap<String, usize> // Position of every label in `strings`
+}
+
+#[derive(Debug, Clone)]
+pub enum Section{
+    LBL1(LBL1),
+    NLI1(NLI1),
+    ATR1(ATR1),
+    TSY1(TSY1),
+    TXT2(TXT2),
+    Unknown(UnknownSection)
 }
 
 #[derive(Debug, Clone, Eq, Ord, PartialEq, PartialOrd)]
 pub struct MSBTString {
     pub index: u32,
-    pub label: String,
-    pub string: Vec<u8>
+    pub label: String, // For files without labels, this is the numeric ID
+    pub id: Option<u32>,
+    pub string: Vec<u8>,
+    pub attributes: Vec<u8>,
+    pub style: Option<u32>
 }
 
+/// Creates an empty MSBT, with the sections a freshly made file needs.
+pub fn new(order: bytestream::ByteOrder, encoding: Encoding, has_labels: bool, has_ids: bool, has_attributes: bool, has_styles: bool) -> MSBT {
+    let mut sections = Vec::<Section>::new();
+    if has_labels {
+        sections.push(Section::LBL1(LBL1::new()));
+    }
+    if has_ids || !has_labels {
+        sections.push(Section::NLI1(NLI1::new()));
+    }
+    if has_attributes {
+        sections.push(Section::ATR1(ATR1::new(Vec::<u8>::new())));
+    }
+    if has_styles {
+        sections.push(Section::TSY1(TSY1::new()));
+    }
+    sections.push(Section::TXT2(TXT2::new()));
+    MSBT {
+        header: Header::new(order, encoding),
+        sections,
+        padding: 0xAB,
+        compression: Compression::None,
+        strings: Vec::<MSBTString>::new(),
+        labels: HashMap::<String, usize>::new()
+    }
+}
+
+/// Reads an MSBT, decompressing it first if it's a Yaz0 or Zstd file.
 pub fn from_binary<R: Read+Seek>(buffer: &mut R) -> Result<MSBT> {
-    let header = Header::read_from(buffer)?;
-    let byte_order = if header.endianness {ByteOrder::BigEndian} else {ByteOrder::LittleEndian};
-    let lbl1 = LBL1::read_from(buffer, byte_order)?;
-    let atr1 = ATR1::read_from(buffer, byte_order)?;
-    let mut has_attributes = true;
-    if atr1.section_size == 0 {
-        has_attributes = false;
+    let compression = Compression::detect(&peek_magic(buffer).map_err(|err| section_error(err, b"MSBT header", 0))?);
+    if compression != Compression::None {
+        debug!("Decompressing {:?} file...", compression);
+        let mut data = Vec::<u8>::new();
+        buffer.read_to_end(&mut data)?;
+        let mut msbt = from_binary(&mut Cursor::new(compression::decompress(&data, compression)?))?;
+        msbt.compression = compression;
+        return Ok(msbt);
     }
-    let txt2 = TXT2::read_from(buffer, byte_order)?;
-    Ok(MSBT { 
-        _header: header,
-        lbl1,
-        _atr1: atr1,
-        txt2,
-        endianness: byte_order,
-        has_attributes
+    let header = Header::read_from(buffer).map_err(|err| section_error(err, b"MSBT header", 0))?;
+    let byte_order = if header.endianness {ByteOrder::BigEndian} else {ByteOrder::LittleEndian};
+    let mut sections = Vec::<Section>::new();
+    let mut padding = None;
+    for _i in 0..header.section_amount {
+        let block_start = buffer.stream_position()?;
+        let magic = peek_magic(buffer).map_err(|err| section_error(err, b"MSBT", block_start))?;
+        let section = match &magic {
+            b"LBL1" => LBL1::read_from(buffer, byte_order).map(Section::LBL1),
+            b"NLI1" => NLI1::read_from(buffer, byte_order).map(Section::NLI1),
+            b"ATR1" => ATR1::read_from(buffer, byte_order).map(Section::ATR1),
+            b"TSY1" => TSY1::read_from(buffer, byte_order).map(Section::TSY1),
+            b"TXT2" => TXT2::read_from(buffer, byte_order).map(Section::TXT2),
+            _ => UnknownSection::read_from(buffer, byte_order).map(Section::Unknown),
+        };
+        sections.push(section.map_err(|err| section_error(err, &magic, block_start))?);
+        if padding.is_none() {
+            padding = read_padding(buffer, block_start, byte_order).map_err(|err| section_error(err, &magic, block_start))?;
         }
-    )
+    }
+    if !sections.iter().any(|s| matches!(s, Section::TXT2(_))) {
+        return Err(Error::MissingSection("TXT2".to_owned()))
+    }
+    let mut msbt = MSBT { 
+        header,
+        sections,
+        padding: padding.unwrap_or(0xAB),
+        compression: Compression::None,
+        strings: Vec::<MSBTString>::new(),
+        labels: HashMap::<String, usize>::new()
+    };
+    msbt.strings = msbt.read_strings()?;
+    msbt.strings.sort_by_key(|s| s.index);
+    msbt.index_labels();
+    Ok(msbt)
+}
+
+fn peek_magic<R: Read+Seek>(buffer: &mut R) -> Result<[u8;4]> {
+    let mut magic = [0u8;4];
+    buffer.read_exact(&mut magic)?;
+    buffer.seek(SeekFrom::Current(-4))?;
+    Ok(magic)
+}
+
+// Running out of file while reading a section is reported with the section's name and start
+fn section_error(err: Error, magic: &[u8], block_start: u64) -> Error {
+    match err {
+        Error::IOError(io_err) if io_err.kind() == ErrorKind::UnexpectedEof => Error::Truncated {
+            section: String::from_utf8_lossy(magic).into_owned(),
+            offset: block_start
+        },
+        err => err,
+    }
 }
 
+// Reads the byte used to pad the section starting at `block_start`, if it needed any.
+fn read_padding<R: Read+Seek>(buffer: &mut R, block_start: u64, order: bytestream::ByteOrder) -> Result<Option<u8>> {
+    let section_end = buffer.stream_position()?;
+    buffer.seek(SeekFrom::Start(block_start+4))?;
+    let data_end = block_start + 0x10 + u32::read_from(buffer, order)? as u64;
+    let mut padding = None;
+    if data_end < section_end {
+        buffer.seek(SeekFrom::Start(data_end))?;
+        padding = Some(u8::read_from(buffer, order)?);
+    }
+    buffer.seek(SeekFrom::Start(section_end))?;
+    Ok(padding)
+}
+
+impl MSBT {
+    // These follow the header and sections, so that they always describe what gets written
+    pub fn endianness(&self) -> bytestream::ByteOrder {
+        if self.header.endianness {ByteOrder::BigEndian} else {ByteOrder::LittleEndian}
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.header.encoding
+    }
+
+    pub fn has_attributes(&self) -> bool {
+        self.atr1().is_some()
+    }
+
+    pub fn has_labels(&self) -> bool {
+        self.lbl1().is_some()
+    }
+
+    pub fn has_styles(&self) -> bool {
+        self.tsy1().is_some()
+    }
+
+    fn lbl1(&self) -> Option<&LBL1> {
+        self.sections.iter().find_map(|s| if let Section::LBL1(lbl1) = s {Some(lbl1)} else {None})
+    }
+
+    fn nli1(&self) -> Option<&NLI1> {
+        self.sections.iter().find_map(|s| if let Section::NLI1(nli1) = s {Some(nli1)} else {None})
+    }
+
+    fn atr1(&self) -> Option<&ATR1> {
+        self.sections.iter().find_map(|s| if let Section::ATR1(atr1) = s {Some(atr1)} else {None})
+    }
+
+    fn tsy1(&self) -> Option<&TSY1> {
+        self.sections.iter().find_map(|s| if let Section::TSY1(tsy1) = s {Some(tsy1)} else {None})
+    }
+
+    fn txt2(&self) -> Option<&TXT2> {
+        self.sections.iter().find_map(|s| if let Section::TXT2(txt2) = s {Some(txt2)} else {None})
+    }
+
+    /// Sections that aren't parsed, in the order they appear in the file.
+    pub fn unknown_sections(&self) -> Vec<&UnknownSection> {
+        self.sections.iter().filter_map(|s| if let Section::Unknown(unknown) = s {Some(unknown)} else {None}).collect()
+    }
+}
+
+/// Returns the strings, in index order.
 pub fn get_strings(msbt: MSBT) -> Result<Vec<MSBTString>> {
-    let mut msbt_strings = Vec::<MSBTString>::new();
-    for label in msbt.lbl1.labels{
-        let string = MSBTString{
-            index: label.string_index,
-            label: label.label,
-            string: msbt.txt2.strings[(label.string_index) as usize].clone(),
+    Ok(msbt.strings)
+}
+
+impl MSBT {
+    // Joins the sections read from a file into strings
+    fn read_strings(&self) -> Result<Vec<MSBTString>> {
+        let mut msbt_strings = Vec::<MSBTString>::new();
+        let empty_txt2 = TXT2::new();
+        let txt2 = self.txt2().unwrap_or(&empty_txt2);
+        let ids = self.nli1().map(|nli1| nli1.entries.clone()).unwrap_or_default();
+        let attributes = self.atr1().map(|atr1| atr1.attributes.clone()).unwrap_or_default();
+        let styles = self.tsy1().map(|tsy1| tsy1.styles.clone()).unwrap_or_default();
+        match self.lbl1() {
+            Some(lbl1) => {
+                for label in &lbl1.labels{
+                    let string = MSBTString{
+                        index: label.string_index,
+                        label: label.label.clone(),
+                        id: ids.iter().find(|e| e.string_index == label.string_index).map(|e| e.id),
+                        string: string_at(txt2, &label.label, label.string_index)?,
+                        attributes: attributes.get(label.string_index as usize).cloned().unwrap_or_default(),
+                        style: styles.get(label.string_index as usize).copied(),
+                    };
+                    msbt_strings.push(string);
+                }
+            }
+            None => {
+                for entry in ids{
+                    let string = MSBTString{
+                        index: entry.string_index,
+                        label: entry.id.to_string(),
+                        id: Some(entry.id),
+                        string: string_at(txt2, &entry.id.to_string(), entry.string_index)?,
+                        attributes: attributes.get(entry.string_index as usize).cloned().unwrap_or_default(),
+                        style: styles.get(entry.string_index as usize).copied(),
+                    };
+                    msbt_strings.push(string);
+                }
+            }
+        }
+        Ok(msbt_strings)
+    }
+
+    fn index_labels(&mut self) {
+        self.labels = self.strings.iter().enumerate().map(|(i, s)| (s.label.clone(), i)).collect();
+    }
+
+    pub fn get(&self, label: &str) -> Option<&MSBTString> {
+        self.labels.get(label).map(|i| &self.strings[*i])
+    }
+
+    /// Strings in index order, i.e. the order of TXT2.
+    pub fn iter(&self) -> impl Iterator<Item = &MSBTString> {
+        self.strings.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    /// Adds a string after the others. Like the ones already there, it gets a zeroed attribute
+    /// record, and its label as ID if the file uses numeric IDs.
+    pub fn insert(&mut self, label: &str, string: Vec<u8>) -> Result<&MSBTString> {
+        if self.labels.contains_key(label) {
+            return Err(Error::DuplicateLabel(label.to_owned()));
+        }
+        let id = if self.has_labels() {new_id(&self.strings, label)} else {Some(numeric_id(label)?)};
+        let new_string = MSBTString {
+            index: self.strings.iter().map(|s| s.index + 1).max().unwrap_or(0),
+            label: label.to_owned(),
+            id,
+            string,
+            attributes: empty_attributes(&self.strings),
+            style: None
         };
-        msbt_strings.push(string);
+        self.labels.insert(new_string.label.clone(), self.strings.len());
+        self.strings.push(new_string);
+        Ok(&self.strings[self.strings.len()-1])
+    }
+
+    /// Replaces the text of a string, as stored in TXT2.
+    pub fn set(&mut self, label: &str, string: Vec<u8>) -> Result<()> {
+        let position = *self.labels.get(label).ok_or_else(|| Error::MissingLabel(label.to_owned()))?;
+        self.strings[position].string = string;
+        Ok(())
+    }
+
+    /// Removes a string. The strings after it move up, so TXT2 has no gap.
+    pub fn remove(&mut self, label: &str) -> Result<MSBTString> {
+        let position = *self.labels.get(label).ok_or_else(|| Error::MissingLabel(label.to_owned()))?;
+        let removed = self.strings.remove(position);
+        for string in &mut self.strings {
+            if string.index > removed.index {
+                string.index -= 1;
+            }
+        }
+        self.index_labels();
+        Ok(removed)
+    }
+
+    /// Changes the label of a string. Files without labels only have IDs, so there the new label
+    /// has to be a number, and becomes the string's ID.
+    pub fn rename(&mut self, label: &str, new_label: &str) -> Result<()> {
+        if label == new_label && self.labels.contains_key(label) {
+            return Ok(());
+        }
+        if self.labels.contains_key(new_label) {
+            return Err(Error::DuplicateLabel(new_label.to_owned()));
+        }
+        let position = *self.labels.get(label).ok_or_else(|| Error::MissingLabel(label.to_owned()))?;
+        if !self.has_labels() {
+            self.strings[position].id = Some(numeric_id(new_label)?);
+        }
+        self.labels.remove(label);
+        self.strings[position].label = new_label.to_owned();
+        self.labels.insert(new_label.to_owned(), position);
+        Ok(())
+    }
+
+    /// Writes the file back with its current strings.
+    pub fn to_binary(&self) -> Result<Vec<u8>> {
+        let mut result = Cursor::new(Vec::<u8>::new());
+        self.write_to(&mut result)?;
+        Ok(result.into_inner())
+    }
+
+    /// Writes the file back with its current strings, along with everything else read from the
+    /// original: header fields, encoding, attribute string table, bucket amount and unknown sections.
+    pub fn write_to<W: Write+Seek>(&self, buffer: &mut W) -> Result<()> {
+        write_to(self, &self.strings, buffer)
+    }
+}
+
+// Labels and IDs come from the file, so the string they point to may not be there
+fn string_at(txt2: &TXT2, label: &str, index: u32) -> Result<Vec<u8>> {
+    txt2.strings.get(index as usize).cloned().ok_or_else(|| Error::LabelIndexOutOfRange {
+        label: label.to_owned(),
+        index,
+        amount: txt2.strings.len()
+    })
+}
+
+/// Returns the ATR1 string table, which the attribute records of the strings may point into.
+pub fn get_attribute_strings(msbt: &MSBT) -> Vec<u8> {
+    msbt.atr1().map(|atr1| atr1.string_table.clone()).unwrap_or_default()
+}
+
+pub fn set_attribute_strings(msbt: &mut MSBT, string_table: Vec<u8>, table_start: Option<u32>) {
+    for section in &mut msbt.sections {
+        if let Section::ATR1(atr1) = section {
+            atr1.string_table = string_table.clone();
+            atr1.table_start = table_start.unwrap_or(0);
+        }
+    }
+}
+
+/// Returns where the ATR1 string table starts. It has to stay there for the records to keep pointing at their strings.
+pub fn get_attribute_table_start(msbt: &MSBT) -> u32 {
+    msbt.atr1().map(|atr1| atr1.table_start).unwrap_or(0)
+}
+
+/// Replaces every string of the file, i.e. with the ones of an extracted document.
+pub fn set_strings(msbt: &mut MSBT, mut msbt_strings: Vec<MSBTString>) {
+    msbt_strings.sort_by_key(|s| s.index);
+    msbt.strings = msbt_strings;
+    msbt.index_labels();
+}
+
+/// Returns the version byte of the header, 3 for every known game.
+pub fn get_version(msbt: &MSBT) -> u8 {
+    msbt.header.version
+}
+
+pub fn set_version(msbt: &mut MSBT, version: u8) {
+    msbt.header.version = version;
+}
+
+/// Returns the sections that aren't parsed, with their position among all the sections of the file.
+pub fn get_unknown_sections(msbt: &MSBT) -> Vec<(usize, UnknownSection)> {
+    msbt.sections.iter().enumerate().filter_map(|(i, s)| if let Section::Unknown(unknown) = s {Some((i, unknown.clone()))} else {None}).collect()
+}
+
+/// Adds a section that isn't parsed at `position` among the sections, or last if there are fewer.
+pub fn add_unknown_section(msbt: &mut MSBT, position: usize, section: UnknownSection) {
+    let position = position.min(msbt.sections.len());
+    msbt.sections.insert(position, Section::Unknown(section));
+}
+
+/// Returns the amount of LBL1 hash buckets, or None if the file has no labels.
+pub fn get_label_slots(msbt: &MSBT) -> Option<u32> {
+    msbt.lbl1().map(|lbl1| lbl1.block_amount)
+}
+
+pub fn set_label_slots(msbt: &mut MSBT, slots: u32) {
+    for section in &mut msbt.sections {
+        if let Section::LBL1(lbl1) = section {
+            lbl1.block_amount = slots.max(1);
+        }
     }
-    Ok(msbt_strings)
 }
 
 pub fn add_string_raw(msbt_strings: &mut Vec<MSBTString>, label: String, string: Vec<u8>) {
-    let last = msbt_strings.iter().map(|c| c.index).max().unwrap();
+    let next = msbt_strings.iter().map(|c| c.index+1).max().unwrap_or(0);
     let new_string = MSBTString{
-        index: last+1,
+        index: next,
+        id: new_id(msbt_strings, &label),
         label,
-        string
+        string,
+        attributes: empty_attributes(msbt_strings),
+        style: None
     };
     msbt_strings.push(new_string);
 }
 
-pub fn add_string(msbt_strings: &mut Vec<MSBTString>, label: String, string: String, order: bytestream::ByteOrder) {
-    let last = msbt_strings.iter().map(|c| c.index).max().unwrap();
-    let new_string: Vec<u8> = match order {
-        ByteOrder::BigEndian => string.encode_utf16().flat_map(|c| c.to_be_bytes()).collect(),
-        ByteOrder::LittleEndian => string.encode_utf16().flat_map(|c| c.to_le_bytes()).collect(),
-    };
+pub fn add_string(msbt_strings: &mut Vec<MSBTString>, label: String, string: String, order: bytestream::ByteOrder, encoding: Encoding) {
+    let next = msbt_strings.iter().map(|c| c.index+1).max().unwrap_or(0);
+    let new_string: Vec<u8> = string.chars().flat_map(|c| convert_char(c, order, encoding)).collect();
     let new_msbt_string = MSBTString{
-        index: last+1,
+        index: next,
+        id: new_id(msbt_strings, &label),
         label,
-        string: new_string
+        string: new_string,
+        attributes: empty_attributes(msbt_strings),
+        style: None
     };
     msbt_strings.push(new_msbt_string);
 }
 
-pub fn delete_string_by_index(msbt_strings: &mut Vec<MSBTString>,index: u32){
-    let vec_index = msbt_strings.iter().position(|s| s.index == index).unwrap();
+/// Splits a string into text, tags and glyphs.
+pub fn get_message(msbt_string: &MSBTString, order: bytestream::ByteOrder, encoding: Encoding) -> Result<Message> {
+    Message::from_binary(&msbt_string.string, order, encoding)
+}
+
+pub fn set_message(msbt_string: &mut MSBTString, message: &Message, order: bytestream::ByteOrder, encoding: Encoding) {
+    msbt_string.string = message.to_binary(order, encoding);
+}
+
+pub fn delete_string_by_index(msbt_strings: &mut Vec<MSBTString>,index: u32) -> Result<()>{
+    let vec_index = msbt_strings.iter().position(|s| s.index == index).ok_or(Error::MissingString(index))?;
     delete_string(msbt_strings, vec_index);
+    Ok(())
 }
 
-pub fn delete_string_by_label(msbt_strings: &mut Vec<MSBTString>,label: String){
-    let vec_index = msbt_strings.iter().position(|s| s.label == label).unwrap();
+pub fn delete_string_by_label(msbt_strings: &mut Vec<MSBTString>,label: String) -> Result<()>{
+    let vec_index = msbt_strings.iter().position(|s| s.label == label).ok_or(Error::MissingLabel(label))?;
     delete_string(msbt_strings, vec_index);
+    Ok(())
 }
 fn delete_string(msbt_strings: &mut Vec<MSBTString>,vec_index: usize){
     let msbt_index = msbt_strings[vec_index].index;
@@ -100,35 +459,313 @@ fn delete_string(msbt_strings: &mut Vec<MSBTString>,vec_index: usize){
     }
 }
 
-pub fn edit_string_by_label(msbt_strings: &mut [MSBTString],label: String, string: String, order: bytestream::ByteOrder){
-    let new_string: Vec<u8> = match order {
-        ByteOrder::BigEndian => string.encode_utf16().flat_map(|c| c.to_be_bytes()).collect(),
-        ByteOrder::LittleEndian => string.encode_utf16().flat_map(|c| c.to_le_bytes()).collect(),
-    };
+pub fn edit_string_by_label(msbt_strings: &mut [MSBTString],label: String, string: String, order: bytestream::ByteOrder, encoding: Encoding){
+    let new_string: Vec<u8> = string.chars().flat_map(|c| convert_char(c, order, encoding)).collect();
+    edit_string_by_label_raw(msbt_strings, label, new_string);
+}
+
+pub fn edit_string_by_label_raw(msbt_strings: &mut [MSBTString],label: String, new_string: Vec<u8>){
     match msbt_strings.iter().position(|s| s.label == label){
         Some(index) => {
-            let old_index = msbt_strings.get(index).unwrap().index;
-            msbt_strings[index] = MSBTString{ index: old_index, label, string:new_string };
+            let old_string = msbt_strings.get(index).unwrap().clone();
+            msbt_strings[index] = MSBTString{ index: old_string.index, label, id: old_string.id, string:new_string, attributes: old_string.attributes, style: old_string.style };
         },
-        None => println!("No label named \"{}\" found!", label),
+        None => warn!("No label named \"{}\" found!", label),
     };
 }
 
 
-pub fn to_binary(msbt_strings: Vec<MSBTString>, order: bytestream::ByteOrder) -> Result<Vec<u8>>{
-    println!("Formatting {} strings to MSBT, {} endian", msbt_strings.len(), match order{
+// New strings get a zeroed attribute record, so that every record in ATR1 keeps the same size.
+fn empty_attributes(msbt_strings: &[MSBTString]) -> Vec<u8> {
+    vec![0u8;msbt_strings.iter().map(|s| s.attributes.len()).max().unwrap_or(0)]
+}
+
+// Without labels, a label is only the ID written as text
+fn numeric_id(label: &str) -> Result<u32> {
+    label.parse().map_err(|_| Error::MissingId(label.to_owned()))
+}
+
+// If the file uses numeric IDs, a new string gets its label as ID, provided it's a number.
+fn new_id(msbt_strings: &[MSBTString], label: &str) -> Option<u32> {
+    if msbt_strings.iter().any(|s| s.id.is_some()) {
+        label.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Writes the strings back, using the sections of `msbt` as a template: every section
+/// is written in its original order, and unknown sections are copied as-is.
+pub fn to_binary(msbt: &MSBT, msbt_strings: Vec<MSBTString>) -> Result<Vec<u8>>{
+    let mut result = Cursor::new(Vec::<u8>::new());
+    write_to(msbt, &msbt_strings, &mut result)?;
+    Ok(result.into_inner())
+}
+
+/// Like `to_binary`, but writes straight to `buffer`. A compressed file is still made in memory first.
+pub fn write_to<W: Write+Seek>(msbt: &MSBT, msbt_strings: &[MSBTString], buffer: &mut W) -> Result<()>{
+    if msbt.compression != Compression::None {
+        let mut file = Cursor::new(Vec::<u8>::new());
+        write_sections(msbt, msbt_strings, &mut file)?;
+        debug!("Compressing to {:?}...", msbt.compression);
+        buffer.write_all(&compression::compress(file.into_inner(), msbt.compression)?)?;
+        return Ok(());
+    }
+    write_sections(msbt, msbt_strings, buffer)
+}
+
+// The header is written last, once the sections are there to be counted
+fn write_sections<W: Write+Seek>(msbt: &MSBT, msbt_strings: &[MSBTString], buffer: &mut W) -> Result<()>{
+    let order = msbt.endianness();
+    debug!("Formatting {} strings to MSBT, {} endian", msbt_strings.len(), match order{
     ByteOrder::BigEndian => "big",
     ByteOrder::LittleEndian => "little",
     });
-    let mut file= Vec::<u8>::new();
-    let mut lbl1 = LBL1::write_binary(msbt_strings.clone(), order)?;
-    let mut atr1 = ATR1::write_binary(msbt_strings.clone(), order)?;
-    let mut txt2 = TXT2::write_binary(msbt_strings.clone(), order)?;
-    let mut header = Header::write_binary(3, (lbl1.len()+atr1.len()+txt2.len()) as u32, order)?;
-    file.append(&mut header);
-    file.append(&mut lbl1);
-    file.append(&mut atr1);
-    file.append(&mut txt2);
-    println!("Formatted MSBT.");
-    Ok(file)
-}
\ No newline at end of file
+    // Without labels, the IDs are the only way to find a string
+    if let Some(string) = msbt_strings.iter().find(|s| !msbt.has_labels() && s.id.is_none()) {
+        return Err(Error::MissingId(string.label.clone()));
+    }
+    let file_start = buffer.stream_position()?;
+    buffer.write_all(&[0u8;0x20])?;
+    let mut section_amount = 0u16;
+    for section in &msbt.sections {
+        match section {
+            Section::LBL1(lbl1) => LBL1::write_to(buffer, msbt_strings, lbl1.block_amount, &lbl1.labels, order)?,
+            Section::NLI1(nli1) => NLI1::write_to(buffer, msbt_strings, &nli1.entries, order)?,
+            // A start of 0 means the table wasn't read from a file, so it can go right after the records
+            Section::ATR1(atr1) => ATR1::write_to(buffer, msbt_strings, &atr1.string_table, Some(atr1.table_start).filter(|&start| start != 0), order)?,
+            Section::TSY1(_) => TSY1::write_to(buffer, msbt_strings, order)?,
+            Section::TXT2(_) => TXT2::write_to(buffer, msbt_strings, order)?,
+            Section::Unknown(unknown) => unknown.write_to(buffer, order)?,
+        };
+        let padding = (16 - (buffer.stream_position()? - file_start) %16) %16;
+        buffer.write_all(&vec![msbt.padding; padding as usize])?;
+        section_amount += 1;
+    }
+    let file_end = buffer.stream_position()?;
+    buffer.seek(SeekFrom::Start(file_start))?;
+    msbt.header.write_to(buffer, section_amount, (file_end - file_start) as u32, msbt.encoding(), order)?;
+    buffer.seek(SeekFrom::Start(file_end))?;
+    debug!("Formatted MSBT.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use crate::sarc::{push_u16, push_u32};
+    use super::*;
+
+    fn utf16(text: &str, order: ByteOrder) -> Vec<u8> {
+        let mut bytes = Vec::<u8>::new();
+        for unit in text.encode_utf16() {
+            push_u16(&mut bytes, unit, order);
+        }
+        bytes
+    }
+
+    // Builds an MSBT by hand, the way a game would ship it, padding sections with 0xAB
+    fn build(order: ByteOrder, sections: &[(&[u8], Vec<u8>)]) -> Vec<u8> {
+        let mut body = Vec::<u8>::new();
+        for (magic, data) in sections {
+            body.extend_from_slice(magic);
+            push_u32(&mut body, data.len() as u32, order);
+            body.extend_from_slice(&[0u8;8]);
+            body.extend_from_slice(data);
+            while !body.len().is_multiple_of(16) {
+                body.push(0xAB);
+            }
+        }
+        let mut file = b"MsgStdBn".to_vec();
+        file.extend_from_slice(match order {
+            ByteOrder::BigEndian => &[0xFE, 0xFF],
+            ByteOrder::LittleEndian => &[0xFF, 0xFE],
+        });
+        push_u16(&mut file, 0, order);
+        file.extend_from_slice(&[1, 3]);
+        push_u16(&mut file, sections.len() as u16, order);
+        push_u16(&mut file, 0, order);
+        push_u32(&mut file, 0x20 + body.len() as u32, order);
+        file.extend_from_slice(&[0u8;10]);
+        file.append(&mut body);
+        file
+    }
+
+    fn txt2(texts: &[&str], order: ByteOrder) -> Vec<u8> {
+        let mut data = Vec::<u8>::new();
+        push_u32(&mut data, texts.len() as u32, order);
+        let mut offset = 4 + texts.len() as u32*4;
+        let strings: Vec<Vec<u8>> = texts.iter().map(|t| utf16(t, order)).collect();
+        for string in &strings {
+            push_u32(&mut data, offset, order);
+            offset += string.len() as u32;
+        }
+        for string in strings {
+            data.extend_from_slice(&string);
+        }
+        data
+    }
+
+    // A single bucket, so that the labels don't have to be sorted by hash
+    fn lbl1(labels: &[&str], order: ByteOrder) -> Vec<u8> {
+        let mut data = Vec::<u8>::new();
+        push_u32(&mut data, 1, order);
+        push_u32(&mut data, labels.len() as u32, order);
+        push_u32(&mut data, 0xC, order);
+        for (i, label) in labels.iter().enumerate() {
+            data.push(label.len() as u8);
+            data.extend_from_slice(label.as_bytes());
+            push_u32(&mut data, i as u32, order);
+        }
+        data
+    }
+
+    // Three strings with 4 byte records, the first two pointing into the string table
+    fn atr1_with_strings(order: ByteOrder) -> Vec<u8> {
+        let mut atr1 = Vec::<u8>::new();
+        push_u32(&mut atr1, 3, order);
+        push_u32(&mut atr1, 4, order);
+        for offset in [20, 24, 0] {
+            push_u32(&mut atr1, offset, order);
+        }
+        atr1.extend_from_slice(b"aaa\0bbb\0");
+        atr1
+    }
+
+    #[test]
+    fn round_trip_with_every_section() {
+        for order in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            let mut atr1 = Vec::<u8>::new();
+            push_u32(&mut atr1, 3, order);
+            push_u32(&mut atr1, 2, order);
+            atr1.extend_from_slice(&[1, 2, 3, 4, 5, 6]);
+            atr1.extend_from_slice(&utf16("x\0", order));
+            let mut tsy1 = Vec::<u8>::new();
+            for style in [7, 0, 9] {
+                push_u32(&mut tsy1, style, order);
+            }
+            let file = build(order, &[
+                (b"LBL1", lbl1(&["First", "Second", "Last"], order)),
+                (b"ATR1", atr1),
+                (b"TSY1", tsy1),
+                (b"ABCD", vec![0xDE, 0xAD, 0xBE]),
+                (b"TXT2", txt2(&["Hi\0", "Yo\0", "Bye\0"], order)),
+            ]);
+            let msbt = from_binary(&mut Cursor::new(&file)).unwrap();
+            let strings = get_strings(msbt.clone()).unwrap();
+            assert_eq!(strings.len(), 3);
+            let second = strings.iter().find(|s| s.label == "Second").unwrap();
+            assert_eq!(second.attributes, vec![3, 4]);
+            assert_eq!(second.style, Some(0));
+            assert_eq!(strings.iter().find(|s| s.label == "Last").unwrap().string, utf16("Bye\0", order));
+            assert_eq!(msbt.unknown_sections().len(), 1);
+            assert_eq!(to_binary(&msbt, strings).unwrap(), file);
+        }
+    }
+
+    #[test]
+    fn round_trip_with_numeric_ids() {
+        let order = ByteOrder::BigEndian;
+        let mut nli1 = Vec::<u8>::new();
+        push_u32(&mut nli1, 2, order);
+        for (id, index) in [(100, 1), (42, 0)] {
+            push_u32(&mut nli1, id, order);
+            push_u32(&mut nli1, index, order);
+        }
+        let file = build(order, &[(b"NLI1", nli1), (b"TXT2", txt2(&["Zero\0", "One\0"], order))]);
+        let msbt = from_binary(&mut Cursor::new(&file)).unwrap();
+        let strings = get_strings(msbt.clone()).unwrap();
+        let mut ids: Vec<_> = strings.iter().map(|s| (s.index, s.id)).collect();
+        ids.sort();
+        assert_eq!(ids, vec![(0, Some(42)), (1, Some(100))]);
+        assert_eq!(to_binary(&msbt, strings).unwrap(), file);
+    }
+
+    #[test]
+    fn attribute_strings_stay_put() {
+        let order = ByteOrder::LittleEndian;
+        let file = build(order, &[
+            (b"LBL1", lbl1(&["A", "B", "C"], order)),
+            (b"ATR1", atr1_with_strings(order)),
+            (b"TXT2", txt2(&["a\0", "b\0", "c\0"], order)),
+        ]);
+        let msbt = from_binary(&mut Cursor::new(&file)).unwrap();
+        assert_eq!(get_attribute_table_start(&msbt), 20);
+
+        // Removing a string leaves a gap, so that the other records still point at their strings
+        let mut strings = get_strings(msbt.clone()).unwrap();
+        delete_string_by_label(&mut strings, "C".to_string()).unwrap();
+        let written = to_binary(&msbt, strings).unwrap();
+        let read = from_binary(&mut Cursor::new(&written)).unwrap();
+        let table_start = get_attribute_table_start(&read) as usize;
+        assert_eq!(get_attribute_strings(&read)[20-table_start..], *b"aaa\0bbb\0");
+
+        // There's no room for another record before the table
+        let mut strings = get_strings(msbt.clone()).unwrap();
+        add_string_raw(&mut strings, "D".to_string(), utf16("d\0", order));
+        assert!(matches!(to_binary(&msbt, strings), Err(Error::AttributeStringsMoved { from: 20, to: 24 })));
+    }
+
+    #[test]
+    fn labels_or_ids_needed() {
+        let order = ByteOrder::LittleEndian;
+        let msbt = new(order, Encoding::UTF16, false, true, false, false);
+        let string = |label: &str, id| MSBTString { index: 0, label: label.to_string(), id, string: utf16("a\0", order), attributes: vec![], style: None };
+        assert!(to_binary(&msbt, vec![string("5", Some(5))]).is_ok());
+        assert!(matches!(to_binary(&msbt, vec![string("Five", None)]), Err(Error::MissingId(label)) if label == "Five"));
+    }
+
+    #[test]
+    fn empty_strings_are_kept() {
+        let order = ByteOrder::LittleEndian;
+        // The empty string shares its offset with the next one
+        let file = build(order, &[
+            (b"LBL1", lbl1(&["A", "Empty", "B"], order)),
+            (b"TXT2", txt2(&["a\0", "", "b\0"], order)),
+        ]);
+        let msbt = from_binary(&mut Cursor::new(&file)).unwrap();
+        let strings = get_strings(msbt.clone()).unwrap();
+        assert_eq!(strings.len(), 3);
+        assert!(strings.iter().find(|s| s.label == "Empty").unwrap().string.is_empty());
+        assert_eq!(strings.iter().find(|s| s.label == "B").unwrap().string, utf16("b\0", order));
+        assert_eq!(to_binary(&msbt, strings).unwrap(), file);
+    }
+
+    #[test]
+    fn edited_file_reads_back() {
+        let mut msbt = new(ByteOrder::LittleEndian, Encoding::UTF16, true, false, true, true);
+        msbt.insert("A", utf16("a\0", ByteOrder::LittleEndian)).unwrap();
+        msbt.insert("B", Vec::<u8>::new()).unwrap();
+        msbt.insert("C", utf16("c\0", ByteOrder::LittleEndian)).unwrap();
+        let file = msbt.to_binary().unwrap();
+        let read = from_binary(&mut Cursor::new(&file)).unwrap();
+        let strings: Vec<_> = read.iter().map(|s| (s.label.as_str(), s.string.clone())).collect();
+        assert_eq!(strings, vec![("A", utf16("a\0", ByteOrder::LittleEndian)), ("B", vec![]), ("C", utf16("c\0", ByteOrder::LittleEndian))]);
+        assert_eq!(read.to_binary().unwrap(), file);
+    }
+
+    #[test]
+    fn labels_are_ids_without_lbl1() {
+        let order = ByteOrder::LittleEndian;
+        let mut msbt = new(order, Encoding::UTF16, false, true, false, false);
+        assert_eq!(msbt.insert("7", utf16("a\0", order)).unwrap().id, Some(7));
+        assert!(matches!(msbt.insert("Seven", utf16("b\0", order)), Err(Error::MissingId(label)) if label == "Seven"));
+        assert!(matches!(msbt.rename("7", "Eight"), Err(Error::MissingId(label)) if label == "Eight"));
+        msbt.rename("7", "8").unwrap();
+        assert_eq!(msbt.get("8").unwrap().id, Some(8));
+        let read = from_binary(&mut Cursor::new(msbt.to_binary().unwrap())).unwrap();
+        assert_eq!(read.get("8").unwrap().string, utf16("a\0", order));
+    }
+
+    // Sizes are patched in once written, wherever the MSBT starts in the stream
+    #[test]
+    fn write_to_after_other_data() {
+        let order = ByteOrder::LittleEndian;
+        let file = build(order, &[(b"LBL1", lbl1(&["Only"], order)), (b"TXT2", txt2(&["Text\0"], order))]);
+        let msbt = from_binary(&mut Cursor::new(&file)).unwrap();
+        let mut buffer = Cursor::new(vec![0xFFu8;5]);
+        buffer.seek(SeekFrom::End(0)).unwrap();
+        msbt.write_to(&mut buffer).unwrap();
+        assert_eq!(&buffer.get_ref()[5..], file.as_slice());
+    }
+}
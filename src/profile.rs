@@ -0,0 +1,44 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use log::debug;
+
+use crate::error::{Error, Result};
+use crate::msbp::TagGroup;
+
+/// Tag definitions for a game, written by hand in TOML. Useful for games that don't ship
+/// their MSBP: the tags are named the same way a project would name them.
+///
+/// ```toml
+/// name = "My game"
+///
+/// [[group]]
+/// id = 1
+/// name = "System"
+///
+/// [[group.tag]]
+/// type = 0
+/// name = "Wait"
+/// params = [{ name = "frames", type = "u16" }]
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Profile{
+    #[serde(default)]
+    pub name: String,
+    #[serde(rename = "group", default)]
+    pub tag_groups: Vec<TagGroup>
+}
+
+pub fn from_toml(text: &str) -> Result<Profile> {
+    let profile: Profile = toml::from_str(text)?;
+    let mut defined = HashSet::<(u16, u16)>::new();
+    for tag_group in &profile.tag_groups {
+        for tag in &tag_group.tags {
+            if !defined.insert((tag_group.id, tag.tag_type)) {
+                return Err(Error::DuplicateTag(tag_group.id, tag.tag_type));
+            }
+        }
+    }
+    debug!("Loaded profile {}: {} tag group(s).", profile.name, profile.tag_groups.len());
+    Ok(profile)
+}
@@ -0,0 +1,352 @@
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+use bytestream::{ByteOrder, StreamReader};
+use log::debug;
+
+use crate::compression::{self, Compression};
+use crate::error::{Error, Result};
+use crate::structs::read_bytes;
+
+const DEFAULT_HASH_KEY: u32 = 0x65;
+const DEFAULT_ALIGNMENT: u32 = 0x10;
+const MAX_ALIGNMENT: u32 = 0x2000;
+
+/// A SARC archive, as used by most games to bundle their MSBT files.
+#[derive(Clone)]
+pub struct SARC{
+    pub endianness: bytestream::ByteOrder,
+    pub hash_key: u32, // Multiplier used to hash the file names, always 0x65 in practice
+    pub files: Vec<SarcFile>, // Sorted by hash, as the nodes have to be
+    version: u16,
+    data_alignment: u32, // Alignment of the data block itself
+    pub compression: Compression
+}
+
+#[derive(Debug, Clone)]
+pub struct SarcFile{
+    pub name: Option<String>, // Path of the file in the archive. Unnamed files are only known by their hash.
+    pub hash: u32,
+    pub data: Vec<u8>,
+    pub alignment: u32 // The start of the file is aligned to this, from the start of the archive
+}
+
+/// Creates an empty archive.
+pub fn new(order: bytestream::ByteOrder) -> SARC {
+    SARC {
+        endianness: order,
+        hash_key: DEFAULT_HASH_KEY,
+        files: Vec::<SarcFile>::new(),
+        version: 0x100,
+        data_alignment: DEFAULT_ALIGNMENT,
+        compression: Compression::None
+    }
+}
+
+/// Reads a SARC archive, decompressing it first if it's a Yaz0 or Zstd file.
+pub fn from_binary<R: Read+Seek>(buffer: &mut R) -> Result<SARC> {
+    let mut data = Vec::<u8>::new();
+    buffer.read_to_end(&mut data)?;
+    let compression = Compression::detect(&data);
+    if compression != Compression::None {
+        debug!("Decompressing {:?} archive...", compression);
+        data = compression::decompress(&data, compression)?;
+    }
+    let mut buffer = Cursor::new(data);
+    debug!("Extracting archive...");
+    let mut magic = vec![0u8;4];
+    buffer.read_exact(&mut magic)?;
+    if magic != b"SARC" {
+        return Err(Error::NotSARC)
+    }
+    buffer.seek(SeekFrom::Current(2))?; // Header size
+    let order = match u16::read_from(&mut buffer, ByteOrder::BigEndian)? {
+        0xFEFF => ByteOrder::BigEndian,
+        _ => ByteOrder::LittleEndian,
+    };
+    let _file_size = u32::read_from(&mut buffer, order)?;
+    let data_offset = u32::read_from(&mut buffer, order)?;
+    let version = u16::read_from(&mut buffer, order)?;
+    buffer.seek(SeekFrom::Current(2))?;
+
+    buffer.read_exact(&mut magic)?;
+    if magic != b"SFAT" {
+        return Err(Error::unexpected_magic(b"SFAT", &magic, buffer.stream_position()?-4))
+    }
+    buffer.seek(SeekFrom::Current(2))?;
+    let node_amount = u16::read_from(&mut buffer, order)?;
+    let hash_key = u32::read_from(&mut buffer, order)?;
+    let mut nodes = Vec::<(u32, u32, u32, u32)>::new(); // (hash, attributes, start, end)
+    for _i in 0..node_amount {
+        nodes.push((
+            u32::read_from(&mut buffer, order)?,
+            u32::read_from(&mut buffer, order)?,
+            u32::read_from(&mut buffer, order)?,
+            u32::read_from(&mut buffer, order)?,
+        ));
+    }
+
+    buffer.read_exact(&mut magic)?;
+    if magic != b"SFNT" {
+        return Err(Error::unexpected_magic(b"SFNT", &magic, buffer.stream_position()?-4))
+    }
+    buffer.seek(SeekFrom::Current(4))?;
+    let names_start = buffer.stream_position()?;
+
+    let mut files = Vec::<SarcFile>::new();
+    for (hash, attributes, start, end) in nodes {
+        //The top byte tells if the file has a name, the rest is where it is, in 4 byte units
+        let name = if attributes >> 24 != 0 {
+            buffer.seek(SeekFrom::Start(names_start + (attributes & 0xFFFFFF) as u64*4))?;
+            let mut name = Vec::<u8>::new();
+            loop {
+                let char = u8::read_from(&mut buffer, order)?;
+                if char == 0 {
+                    break;
+                }
+                name.push(char);
+            }
+            Some(String::from_utf8(name)?)
+        } else {
+            None
+        };
+        if end < start {
+            return Err(Error::OffsetOutOfRange { section: "SFAT".to_owned(), offset: data_offset as u64 + end as u64 })
+        }
+        let file_start = data_offset as u64 + start as u64;
+        buffer.seek(SeekFrom::Start(file_start))?;
+        let data = read_bytes(&mut buffer, (end-start) as u64, "SARC")?;
+        files.push(SarcFile {
+            name,
+            hash,
+            data,
+            alignment: guess_alignment(file_start as u32)
+        });
+    }
+    debug!("Extracted {} file(s).", files.len());
+    Ok(SARC {
+        endianness: order,
+        hash_key,
+        files,
+        version,
+        data_alignment: guess_alignment(data_offset),
+        compression
+    })
+}
+
+/// Writes the archive back. Files keep the alignment they had, so an untouched archive
+/// comes out the same as it went in.
+pub fn to_binary(sarc: &SARC) -> Result<Vec<u8>> {
+    let order = sarc.endianness;
+    debug!("Formatting {} file(s) to SARC...", sarc.files.len());
+    let mut names = Vec::<u8>::new();
+    let mut nodes = Vec::<u8>::new();
+    let mut data = Vec::<u8>::new();
+    let names_start = 0x14 + 0xC + sarc.files.len() as u32*0x10 + 0x8;
+    let names_size: u32 = sarc.files.iter().filter_map(|f| f.name.as_ref()).map(|n| align(n.len() as u32+1, 4)).sum();
+    let data_offset = align(names_start + names_size, sarc.data_alignment);
+    for file in &sarc.files {
+        let attributes = match &file.name {
+            Some(name) => {
+                let attributes = 0x01000000 | (names.len() as u32/4);
+                names.append(&mut name.as_bytes().to_vec());
+                names.push(0);
+                while !names.len().is_multiple_of(4) {
+                    names.push(0);
+                }
+                attributes
+            }
+            None => 0,
+        };
+        let start = align(data_offset + data.len() as u32, file.alignment) - data_offset;
+        data.resize(start as usize, 0);
+        data.append(&mut file.data.clone());
+        push_u32(&mut nodes, file.hash, order);
+        push_u32(&mut nodes, attributes, order);
+        push_u32(&mut nodes, start, order);
+        push_u32(&mut nodes, data.len() as u32, order);
+    }
+
+    //binary tiem
+    let mut result = b"SARC".to_vec();
+    push_u16(&mut result, 0x14, order);
+    match order {
+        ByteOrder::BigEndian => result.append(&mut vec![0xFE, 0xFF]),
+        ByteOrder::LittleEndian => result.append(&mut vec![0xFF, 0xFE]),
+    }
+    push_u32(&mut result, data_offset + data.len() as u32, order);
+    push_u32(&mut result, data_offset, order);
+    push_u16(&mut result, sarc.version, order);
+    push_u16(&mut result, 0, order);
+    result.append(&mut b"SFAT".to_vec());
+    push_u16(&mut result, 0xC, order);
+    push_u16(&mut result, sarc.files.len() as u16, order);
+    push_u32(&mut result, sarc.hash_key, order);
+    result.append(&mut nodes);
+    result.append(&mut b"SFNT".to_vec());
+    push_u16(&mut result, 0x8, order);
+    push_u16(&mut result, 0, order);
+    result.append(&mut names);
+    result.resize(data_offset as usize, 0);
+    result.append(&mut data);
+    debug!("Formatted SARC.");
+    if sarc.compression != Compression::None {
+        debug!("Compressing to {:?}...", sarc.compression);
+    }
+    compression::compress(result, sarc.compression)
+}
+
+impl SARC {
+    pub fn get_file(&self, name: &str) -> Option<&SarcFile> {
+        self.files.iter().find(|f| f.name.as_deref() == Some(name))
+    }
+
+    /// Replaces the content of a file, or adds it if there's no file with that name.
+    pub fn set_file(&mut self, name: &str, data: Vec<u8>) {
+        if let Some(file) = self.files.iter_mut().find(|f| f.name.as_deref() == Some(name)) {
+            file.data = data;
+            return;
+        }
+        self.files.push(SarcFile {
+            name: Some(name.to_owned()),
+            hash: calculate_hash(name, self.hash_key),
+            data,
+            alignment: DEFAULT_ALIGNMENT
+        });
+        self.files.sort_by_key(|f| f.hash);
+    }
+
+    /// Names of every MSBT in the archive, in archive order.
+    pub fn msbt_files(&self) -> Vec<String> {
+        self.files.iter()
+            .filter(|f| f.data.starts_with(b"MsgStdBn"))
+            .filter_map(|f| f.name.clone())
+            .collect()
+    }
+}
+
+/// Hash of a file name, used to sort and look up the nodes.
+pub fn calculate_hash(name: &str, key: u32) -> u32 {
+    let mut hash: u32 = 0;
+    for char in name.as_bytes() {
+        hash = hash.wrapping_mul(key).wrapping_add(*char as u32);
+    }
+    hash
+}
+
+// Biggest alignment the offset satisfies. Files that were aligned to this will stay
+// where they were as long as nothing before them changes.
+fn guess_alignment(offset: u32) -> u32 {
+    if offset == 0 {
+        return MAX_ALIGNMENT;
+    }
+    (1 << offset.trailing_zeros()).min(MAX_ALIGNMENT)
+}
+
+fn align(value: u32, alignment: u32) -> u32 {
+    value.div_ceil(alignment.max(1)) * alignment.max(1)
+}
+
+pub(crate) fn push_u16(result: &mut Vec<u8>, value: u16, order: bytestream::ByteOrder) {
+    match order {
+        ByteOrder::BigEndian => result.append(&mut value.to_be_bytes().to_vec()),
+        ByteOrder::LittleEndian => result.append(&mut value.to_le_bytes().to_vec()),
+    }
+}
+
+pub(crate) fn push_u32(result: &mut Vec<u8>, value: u32, order: bytestream::ByteOrder) {
+    match order {
+        ByteOrder::BigEndian => result.append(&mut value.to_be_bytes().to_vec()),
+        ByteOrder::LittleEndian => result.append(&mut value.to_le_bytes().to_vec()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Builds an archive by hand, with its data at 0x100 and the files at the given offsets in it
+    fn build(order: ByteOrder, files: &[(Option<&str>, u32, &[u8])]) -> Vec<u8> {
+        let mut files: Vec<_> = files.iter().map(|(name, start, data)| (name.map(|n| calculate_hash(n, DEFAULT_HASH_KEY)).unwrap_or(0xFFFFFFFF), *name, *start, *data)).collect();
+        files.sort_by_key(|f| f.0);
+        let mut nodes = Vec::<u8>::new();
+        let mut names = Vec::<u8>::new();
+        let mut data = Vec::<u8>::new();
+        for (hash, name, start, file) in files {
+            push_u32(&mut nodes, hash, order);
+            match name {
+                Some(name) => {
+                    push_u32(&mut nodes, 0x01000000 | (names.len() as u32/4), order);
+                    names.extend_from_slice(name.as_bytes());
+                    names.push(0);
+                    names.resize(align(names.len() as u32, 4) as usize, 0);
+                }
+                None => push_u32(&mut nodes, 0, order),
+            }
+            push_u32(&mut nodes, start, order);
+            push_u32(&mut nodes, start + file.len() as u32, order);
+            data.resize(start as usize, 0);
+            data.extend_from_slice(file);
+        }
+        let mut result = b"SARC".to_vec();
+        push_u16(&mut result, 0x14, order);
+        push_u16(&mut result, 0xFEFF, order);
+        push_u32(&mut result, 0x100 + data.len() as u32, order);
+        push_u32(&mut result, 0x100, order);
+        push_u16(&mut result, 0x100, order);
+        push_u16(&mut result, 0, order);
+        result.extend_from_slice(b"SFAT");
+        push_u16(&mut result, 0xC, order);
+        push_u16(&mut result, (nodes.len()/0x10) as u16, order);
+        push_u32(&mut result, DEFAULT_HASH_KEY, order);
+        result.append(&mut nodes);
+        result.extend_from_slice(b"SFNT");
+        push_u16(&mut result, 0x8, order);
+        push_u16(&mut result, 0, order);
+        result.append(&mut names);
+        result.resize(0x100, 0);
+        result.append(&mut data);
+        result
+    }
+
+    #[test]
+    fn round_trip() {
+        for order in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            let archive = build(order, &[
+                (Some("Msg/A.msbt"), 0, b"MsgStdBn A"),
+                (Some("Msg/Bee.msbt"), 0x80, b"MsgStdBn Bee"),
+                (None, 0x100, b"unnamed"),
+            ]);
+            let sarc = from_binary(&mut Cursor::new(&archive)).unwrap();
+            assert_eq!(sarc.msbt_files().len(), 2);
+            assert_eq!(sarc.get_file("Msg/Bee.msbt").unwrap().data, b"MsgStdBn Bee");
+            assert_eq!(to_binary(&sarc).unwrap(), archive);
+        }
+    }
+
+    #[test]
+    fn files_keep_their_alignment() {
+        let archive = build(ByteOrder::LittleEndian, &[(Some("A"), 0, b"short"), (Some("B"), 0x80, b"aligned")]);
+        let mut sarc = from_binary(&mut Cursor::new(&archive)).unwrap();
+        sarc.set_file("A", vec![1u8;0x90]);
+        sarc.set_file("C", b"new".to_vec());
+        let edited = to_binary(&sarc).unwrap();
+        let read = from_binary(&mut Cursor::new(&edited)).unwrap();
+        assert_eq!(read.get_file("A").unwrap().data, vec![1u8;0x90]);
+        assert_eq!(read.get_file("C").unwrap().data, b"new");
+        let b = read.get_file("B").unwrap();
+        assert_eq!(b.data, b"aligned");
+        assert_eq!(b.alignment % 0x80, 0);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compressed_round_trip() {
+        let archive = build(ByteOrder::BigEndian, &[(Some("A.msbt"), 0, b"MsgStdBn A")]);
+        let mut sarc = from_binary(&mut Cursor::new(&archive)).unwrap();
+        sarc.compression = Compression::Yaz0;
+        let compressed = to_binary(&sarc).unwrap();
+        let read = from_binary(&mut Cursor::new(&compressed)).unwrap();
+        assert_eq!(read.compression, Compression::Yaz0);
+        assert_eq!(compression::decompress(&compressed, Compression::Yaz0).unwrap(), archive);
+    }
+}
@@ -1,9 +1,49 @@
 pub mod header;
 pub mod lbl1;
+pub mod nli1;
 pub mod atr1;
+pub mod tsy1;
 pub mod txt2;
+pub mod unknown;
 
-pub use header::Header;
+pub use header::{Encoding, Header};
 pub use lbl1::LBL1;
+pub use nli1::NLI1;
 pub use atr1::ATR1;
+pub use tsy1::TSY1;
 pub use txt2::TXT2;
+pub use unknown::UnknownSection;
+
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use bytestream::StreamWriter;
+
+use crate::error::{Error, Result};
+
+/// Reads `size` bytes of `section`. The size comes from the file, so it isn't used to
+/// allocate up front: a size past the end of the file is an error, not a huge buffer.
+pub(crate) fn read_bytes<R: Read + Seek>(buffer: &mut R, size: u64, section: &str) -> Result<Vec<u8>> {
+    let offset = buffer.stream_position()?;
+    let mut data = Vec::<u8>::new();
+    buffer.by_ref().take(size).read_to_end(&mut data)?;
+    if (data.len() as u64) < size {
+        return Err(Error::Truncated { section: section.to_owned(), offset: offset + data.len() as u64 });
+    }
+    Ok(data)
+}
+
+/// Writes a section: its magic, its size, and the data `write_data` writes after the 0x10 byte
+/// section header. The size is the amount of data actually written, filled in afterwards.
+pub(crate) fn write_section<W, F>(buffer: &mut W, magic: &[u8], order: bytestream::ByteOrder, write_data: F) -> Result<()>
+where W: Write + Seek, F: FnOnce(&mut W) -> Result<()> {
+    let block_start = buffer.stream_position()?;
+    buffer.write_all(magic)?;
+    0u32.write_to(buffer, order)?;
+    buffer.write_all(&[0u8;8])?;
+    write_data(buffer)?;
+    let block_end = buffer.stream_position()?;
+    buffer.seek(SeekFrom::Start(block_start+4))?;
+    ((block_end - block_start - 0x10) as u32).write_to(buffer, order)?;
+    buffer.seek(SeekFrom::Start(block_end))?;
+    Ok(())
+}
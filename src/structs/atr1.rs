@@ -1,63 +1,97 @@
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Read, Seek, SeekFrom, Write};
 
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::msbt::MSBTString;
-use bytestream::{ByteOrder, StreamReader};
+use crate::structs::{read_bytes, write_section};
+use bytestream::{StreamReader, StreamWriter};
+use log::debug;
 
 #[derive(Debug, Clone)]
-pub struct ATR1{ // Not enough data, since only Rhythm Heaven Megamix has been used for research.
+pub struct ATR1{
     _magic: Vec<u8>,
     pub section_size: u32,
-    pub string_amount:u64
+    pub string_amount: u32,
+    pub attribute_size: u32, // Size of a single attribute record, 0 if the strings have no attributes
+    pub attributes: Vec<Vec<u8>>, // One record per string, in TXT2 order
+    pub table_start: u32, // Where the string table starts, from the string amount on. 0 when not read from a file
+    pub string_table: Vec<u8> // Whatever follows the records. Offsets in the records point in here.
 }
 
 impl ATR1{
+    pub fn new(string_table: Vec<u8>) -> ATR1 {
+        ATR1 { _magic: b"ATR1".to_vec(), section_size: 0, string_amount: 0, attribute_size: 0, attributes: Vec::<Vec<u8>>::new(), table_start: 0, string_table }
+    }
+
     pub fn read_from<R: Read + Seek>(buffer: &mut R, order: bytestream::ByteOrder) -> Result<ATR1> {
-        println!("Extracting attributes...");
+        debug!("Extracting attributes...");
         let block_start = buffer.stream_position()?;
         let mut magic = vec![0u8;4];
         buffer.read_exact(&mut magic)?;
         if magic != b"ATR1" {
             buffer.seek(SeekFrom::Current(-4))?;
-            println!("No ATR1 section, continuing...");
-            return Ok(ATR1{ _magic: "NONE".as_bytes().to_vec(), section_size: 0, string_amount: 0 });
+            debug!("No ATR1 section, continuing...");
+            return Ok(ATR1{
+                _magic: "NONE".as_bytes().to_vec(),
+                section_size: 0,
+                string_amount: 0,
+                attribute_size: 0,
+                attributes: Vec::<Vec<u8>>::new(),
+                table_start: 0,
+                string_table: Vec::<u8>::new()
+            });
         }
         let section_size = u32::read_from(buffer, order)?;
         buffer.seek(SeekFrom::Current(8))?;
-        let string_amount = u64::read_from(buffer, order)?;
-        buffer.seek(SeekFrom::Start(block_start+0x10+section_size as u64+(0x10-(section_size%0x10)) as u64))?;
-        println!("Extracted attributes.");
-        Ok(ATR1 { 
+        let string_amount = u32::read_from(buffer, order)?;
+        let attribute_size = u32::read_from(buffer, order)?;
+        let mut attributes = Vec::<Vec<u8>>::new();
+        // Strings without a record read as having an empty one, so empty records aren't kept
+        for _i in 0..if attribute_size != 0 {string_amount} else {0} {
+            attributes.push(read_bytes(buffer, attribute_size as u64, "ATR1")?);
+        }
+        let table_size = (section_size as u64).saturating_sub(8+string_amount as u64*attribute_size as u64);
+        let string_table = read_bytes(buffer, table_size, "ATR1")?;
+        let leftover_padding = if section_size%0x10 != 0 {0x10-(section_size%0x10)} else {0};
+        buffer.seek(SeekFrom::Start(block_start+0x10+section_size as u64+(leftover_padding) as u64))?;
+        debug!("Extracted attributes.");
+        Ok(ATR1 {
             _magic: magic,
             section_size,
-            string_amount
+            string_amount,
+            attribute_size,
+            attributes,
+            table_start: 8+string_amount*attribute_size,
+            string_table
         })
     }
 
-    pub fn write_binary(msbt_strings: Vec<MSBTString>, order: bytestream::ByteOrder) -> Result<Vec<u8>>{
-        println!("Formatting attributes...");
-        let mut result = Vec::<u8>::new();
-        let section_size = 8_u32;
+    /// Writes the records, then the string table. Records point into the table, so when `table_start` is
+    /// given the table stays there: fewer records leave a gap before it, and more don't fit.
+    pub fn write_to<W: Write + Seek>(buffer: &mut W, msbt_strings: &[MSBTString], string_table: &[u8], table_start: Option<u32>, order: bytestream::ByteOrder) -> Result<()>{
+        debug!("Formatting attributes...");
+        let mut new_strings: Vec<&MSBTString> = msbt_strings.iter().collect();
+        new_strings.sort_by_key(|s| s.index);
+        //Every record has the same size, so the biggest one wins and the others are zero-filled
+        let attribute_size = new_strings.iter().map(|s| s.attributes.len()).max().unwrap_or(0);
+        let records_end = 8 + (new_strings.len()*attribute_size) as u32;
+        let gap = match table_start.filter(|_| !string_table.is_empty()) {
+            Some(table_start) if records_end > table_start => return Err(Error::AttributeStringsMoved { from: table_start, to: records_end }),
+            Some(table_start) => table_start - records_end,
+            None => 0,
+        };
         //binary tiem
-        result.append(&mut b"ATR1".to_vec());
-        match order {
-            ByteOrder::BigEndian => {
-                result.append(&mut section_size.to_be_bytes().to_vec());
-                result.append(&mut vec![0,0,0,0,0,0,0,0]);
-                result.append(&mut (msbt_strings.len()).to_be_bytes().to_vec());
-            }
-            ByteOrder::LittleEndian => {
-                result.append(&mut section_size.to_le_bytes().to_vec());
-                result.append(&mut vec![0,0,0,0,0,0,0,0]);
-                result.append(&mut (msbt_strings.len()).to_le_bytes().to_vec());
+        write_section(buffer, b"ATR1", order, |buffer| {
+            (new_strings.len() as u32).write_to(buffer, order)?;
+            (attribute_size as u32).write_to(buffer, order)?;
+            for string in new_strings {
+                buffer.write_all(&string.attributes)?;
+                buffer.write_all(&vec![0u8;attribute_size - string.attributes.len()])?;
             }
-        }
-        let padding = 16 - result.len() %16;
-        for _i in 0..padding{
-            result.push(0xD0);
-        }
-
-        println!("Formated attributes.");
-        Ok(result)
+            buffer.write_all(&vec![0u8;gap as usize])?;
+            buffer.write_all(string_table)?;
+            Ok(())
+        })?;
+        debug!("Formated attributes.");
+        Ok(())
     }
 }
\ No newline at end of file
@@ -1,22 +1,79 @@
-use std::io::{Read, Seek, SeekFrom};
-use bytestream::{ByteOrder, StreamReader};
+use std::io::{Read, Seek, Write};
+use bytestream::{ByteOrder, StreamReader, StreamWriter};
+use log::debug;
+use serde::{Deserialize, Serialize};
 use crate::error::{Error, Result};
 
 #[derive(Debug, Clone)]
 pub struct Header{
     _magic: Vec<u8>,
     pub endianness: bool,
-    _unk1: u16, // ??? Always 0
-    _encoding: u8, // Message encoding (0=UTF-8, 1=UTF-16, 2=UTF-32)
-    _version: u8, // Version? Always 03
+    unk1: u16, // ??? Always 0
+    pub encoding: Encoding, // Message encoding (0=UTF-8, 1=UTF-16, 2=UTF-32)
+    pub version: u8, // Version? Always 03
     pub section_amount: u16,
-    _unk3: u16,
+    unk3: u16,
     _filesize: u32,
+    reserved: Vec<u8> // 10 bytes, always 0 as far as we know
+}
+
+/// Encoding of the strings in TXT2, as stored in the header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Encoding{
+    #[serde(rename = "UTF-8")]
+    UTF8,
+    #[default]
+    #[serde(rename = "UTF-16")]
+    UTF16,
+    #[serde(rename = "UTF-32")]
+    UTF32
+}
+
+impl Encoding{
+    pub fn from_byte(byte: u8) -> Result<Encoding> {
+        match byte {
+            0 => Ok(Encoding::UTF8),
+            1 => Ok(Encoding::UTF16),
+            2 => Ok(Encoding::UTF32),
+            _ => Err(Error::UnknownEncoding(byte))
+        }
+    }
+
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Encoding::UTF8 => 0,
+            Encoding::UTF16 => 1,
+            Encoding::UTF32 => 2,
+        }
+    }
+
+    /// Size of a single code unit, in bytes.
+    pub fn unit_size(self) -> usize {
+        match self {
+            Encoding::UTF8 => 1,
+            Encoding::UTF16 => 2,
+            Encoding::UTF32 => 4,
+        }
+    }
 }
 
 impl Header{
+    pub fn new(order: bytestream::ByteOrder, encoding: Encoding) -> Header {
+        Header{
+            _magic: b"MsgStdBn".to_vec(),
+            endianness: matches!(order, ByteOrder::BigEndian),
+            unk1: 0,
+            encoding,
+            version: 3,
+            section_amount: 0,
+            unk3: 0,
+            _filesize: 0,
+            reserved: vec![0u8;10]
+        }
+    }
+
     pub fn read_from<R: Read+Seek>(buffer: &mut R) -> Result<Header> {
-        println!("Extracting header...");
+        debug!("Extracting header...");
         let mut magic = vec![0u8;8];
         buffer.read_exact(&mut magic)?;
         if magic != b"MsgStdBn" {
@@ -32,46 +89,35 @@ impl Header{
             endianness = bytestream::ByteOrder::LittleEndian;
             endianness_bool = false;
         }
-        buffer.seek(SeekFrom::Current(10))?;
-        println!("Extracted header.");
-        Ok(Header{
+        let mut header = Header{
             _magic: magic,
             endianness: endianness_bool,
-            _unk1: u16::read_from(buffer, endianness)?,
-            _encoding: u8::read_from(buffer, endianness)?,
-            _version: u8::read_from(buffer, endianness)?,
+            unk1: u16::read_from(buffer, endianness)?,
+            encoding: Encoding::from_byte(u8::read_from(buffer, endianness)?)?,
+            version: u8::read_from(buffer, endianness)?,
             section_amount: u16::read_from(buffer, endianness)?,
-            _unk3: u16::read_from(buffer, endianness)?,
+            unk3: u16::read_from(buffer, endianness)?,
             _filesize: u32::read_from(buffer, endianness)?,
-        })
+            reserved: vec![0u8;10]
+        };
+        buffer.read_exact(&mut header.reserved)?;
+        debug!("Extracted header.");
+        Ok(header)
     }
 
-    pub fn write_binary(section_amount: u16,section_sizes: u32, order: bytestream::ByteOrder) -> Result<Vec<u8>>{
-        println!("Formatting header...");
-        let mut result = Vec::<u8>::new();
+    /// Writes the header back, keeping the fields we don't know about from the original file.
+    pub fn write_to<W: Write>(&self, buffer: &mut W, section_amount: u16, file_size: u32, encoding: Encoding, order: bytestream::ByteOrder) -> Result<()>{
+        debug!("Formatting header...");
         //binary tiem
-        result.append(&mut b"MsgStdBn".to_vec());
-        match order {
-            ByteOrder::BigEndian => {
-                result.append(&mut vec![0xFE, 0xFF]);
-                result.append(&mut vec![0,0,1,3]);
-                result.append(&mut section_amount.to_be_bytes().to_vec());
-                result.append(&mut vec![0,0]);
-                result.append(&mut (section_sizes+0x20).to_be_bytes().to_vec());
-            }
-            ByteOrder::LittleEndian => {
-                result.append(&mut vec![0xFF, 0xFE]);
-                result.append(&mut vec![0,0,1,3]);
-                result.append(&mut section_amount.to_le_bytes().to_vec());
-                result.append(&mut vec![0,0]);
-                result.append(&mut (section_sizes+0x20).to_le_bytes().to_vec());
-            }
-        }
-        let padding = 16 - result.len() %16;
-        for _i in 0..padding{
-            result.push(0x0);
-        }
-        println!("Formated header.");
-        Ok(result)
+        buffer.write_all(b"MsgStdBn")?;
+        0xFEFFu16.write_to(buffer, order)?;
+        self.unk1.write_to(buffer, order)?;
+        buffer.write_all(&[encoding.to_byte(),self.version])?;
+        section_amount.write_to(buffer, order)?;
+        self.unk3.write_to(buffer, order)?;
+        file_size.write_to(buffer, order)?;
+        buffer.write_all(&self.reserved)?;
+        debug!("Formated header.");
+        Ok(())
     }
 }
\ No newline at end of file
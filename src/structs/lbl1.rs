@@ -1,12 +1,14 @@
-use std::io::{Read, Seek, SeekFrom};
-use bytestream::{ByteOrder, StreamReader};
-use crate::{error::{Error, Result}, msbt::MSBTString};
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom, Write};
+use bytestream::{StreamReader, StreamWriter};
+use log::debug;
+use crate::{error::{Error, Result}, msbt::MSBTString, structs::write_section};
 
 #[derive(Debug, Clone)]
 pub struct LBL1{
     _magic: Vec::<u8>,
     _section_size: u32,
-    _block_amount: u32,
+    pub block_amount: u32, // Amount of hash buckets
     pub offsets: Vec<LabelDef>,
     pub labels: Vec<Label>
 }
@@ -22,17 +24,27 @@ pub struct Label{
     pub size: u8,
     pub label: String,
     pub string_index: u32,
-    hash: u8
+    pub hash: u32
+}
+
+impl Default for LBL1 {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl LBL1 {
+    pub fn new() -> LBL1 {
+        LBL1 { _magic: b"LBL1".to_vec(), _section_size: 0, block_amount: 101, offsets: Vec::<LabelDef>::new(), labels: Vec::<Label>::new() }
+    }
+
     pub fn read_from<R: Read + Seek>(buffer: &mut R, order: bytestream::ByteOrder) -> Result<LBL1> {
-        println!("Extracting labels...");
+        debug!("Extracting labels...");
         let block_start = buffer.stream_position()?;
         let mut magic = vec![0u8;4];
         buffer.read_exact(&mut magic)?;
         if magic != b"LBL1" {
-            return Err(Error::MalformedFile)
+            return Err(Error::unexpected_magic(b"LBL1", &magic, block_start))
         }
         let section_size = u32::read_from(buffer, order)?;
         buffer.seek(SeekFrom::Current(8))?;
@@ -40,19 +52,29 @@ impl LBL1 {
         let block_amount = u32::read_from(buffer, order)?;
         let label_defs = Self::get_offsets(buffer, order, block_amount)?;
         buffer.seek(SeekFrom::Start(start_block))?;
-        let labels = Self::get_labels(buffer, order, label_defs.clone())?;
+        let labels = Self::get_labels(buffer, order, label_defs.clone(), block_amount)?;
         let leftover_padding = if section_size%0x10 != 0 {0x10-(section_size%0x10)} else {0};
         buffer.seek(SeekFrom::Start(block_start+0x10+section_size as u64+(leftover_padding) as u64))?;
-        println!("Extracted labels.");
+        debug!("Extracted labels.");
         Ok(LBL1{
             _magic: magic,
             _section_size: section_size,
-            _block_amount: block_amount,
+            block_amount,
             offsets: label_defs,
             labels
         })
     }
 
+    /// Reads a bare hash table, starting at its bucket amount. MSBP files use the same
+    /// tables to name their colours, attributes and styles.
+    pub fn read_table<R: Read + Seek>(buffer: &mut R, order: bytestream::ByteOrder) -> Result<Vec<Label>> {
+        let start_block = buffer.stream_position()?;
+        let block_amount = u32::read_from(buffer, order)?;
+        let label_defs = Self::get_offsets(buffer, order, block_amount)?;
+        buffer.seek(SeekFrom::Start(start_block))?;
+        Self::get_labels(buffer, order, label_defs, block_amount)
+    }
+
     fn get_offsets<R:Read>(buffer: &mut R, order: bytestream::ByteOrder, amount: u32) -> Result<Vec<LabelDef>> {
         let mut offsets = Vec::<LabelDef>::new();
         for _i in 0..amount {
@@ -66,7 +88,7 @@ impl LBL1 {
         Ok(offsets)
     }
 
-    fn get_labels<R:Read+ Seek>(buffer: &mut R, order: bytestream::ByteOrder, label_defs: Vec<LabelDef>) -> Result<Vec<Label>> {
+    fn get_labels<R:Read+ Seek>(buffer: &mut R, order: bytestream::ByteOrder, label_defs: Vec<LabelDef>, slots: u32) -> Result<Vec<Label>> {
         let mut labels = Vec::<Label>::new();
         let start_pos = buffer.stream_position()?;
         for label_def in label_defs {
@@ -80,130 +102,52 @@ impl LBL1 {
                     size,
                     label: String::from_utf8(string.clone())?,
                     string_index: index,
-                    hash: Self::calculate_hash(String::from_utf8(string)?) as u8
+                    hash: Self::calculate_hash(&String::from_utf8(string)?, slots)
                 });
             }
         }
         Ok(labels)
     }
-    pub fn write_binary(msbt_strings: Vec<MSBTString>, order: bytestream::ByteOrder) -> Result<Vec<u8>> {
-        println!("Formatting labels...");
-        let mut result = Vec::<u8>::new();
-        let mut labels = Vec::<Label>::new();
-        let mut label_defs = vec![LabelDef{ amount: 0, offset: 0 };101];
-        let mut labels_raw = Vec::<u8>::new();
-        let base_offset = 0x32C; //Always the same for MSBT
-        let mut label_defs_raw = Vec::<u8>::new();
-
-        for string in msbt_strings {
-            //Calculate the hash
-            let hash = Self::calculate_hash(string.label.clone());
-            let label = Label{
-                size: string.label.len() as u8,
-                label: string.label,
-                string_index: string.index,
-                hash: hash as u8,
-            };
-            labels.push(label);
-        }
-        //Now we prepare the offset array (vector of LabelDef)/the label array (raw)
-        labels.sort_by(|a,b| a.hash.cmp(&b.hash));
-        let mut label_def = LabelDef{amount: 0, offset: base_offset};
-        let mut current_hash = 0;
-        for label in labels{
-            if label.hash != current_hash{
-                label_defs.insert(current_hash as usize, label_def);
-                label_def = LabelDef{amount: 0, offset: base_offset+labels_raw.len() as u32};
-                current_hash = label.hash;
-            }
-            label_def.amount += 1;
-            labels_raw.push(label.size);
-            labels_raw.append(&mut label.label.as_bytes().to_vec());
-            match order {
-                ByteOrder::BigEndian => {labels_raw.append(&mut label.string_index.to_be_bytes().to_vec());}
-                ByteOrder::LittleEndian => {labels_raw.append(&mut label.string_index.to_le_bytes().to_vec());}
-            }
-        }
-        label_defs.insert(current_hash as usize, label_def);
-        //Finally, we make the raw offset array
-        let mut empties = 0u8;
-        for i in 0..101{
-            let label_def = label_defs.get(i).unwrap();
-            if label_def.offset != 0 {
-                for _i in 0..empties{
-                    match order {
-                        ByteOrder::BigEndian => {
-                            label_defs_raw.append(&mut 0u32.to_be_bytes().to_vec());
-                            label_defs_raw.append(&mut label_def.offset.to_be_bytes().to_vec());
-                        }
-                        ByteOrder::LittleEndian => {
-                            label_defs_raw.append(&mut 0u32.to_le_bytes().to_vec());
-                            label_defs_raw.append(&mut label_def.offset.to_le_bytes().to_vec());
-                        }
-                    }
-                }
-                match order {
-                    ByteOrder::BigEndian => {
-                        label_defs_raw.append(&mut label_def.amount.to_be_bytes().to_vec());
-                        label_defs_raw.append(&mut label_def.offset.to_be_bytes().to_vec());
-                    }
-                    ByteOrder::LittleEndian => {
-                        label_defs_raw.append(&mut label_def.amount.to_le_bytes().to_vec());
-                        label_defs_raw.append(&mut label_def.offset.to_le_bytes().to_vec());
-                    }
-                }
-                empties = 0;
-            } else {
-                empties += 1;
-            }
-        }
-        //Don't forget to fill it back up again!
-        for _i in 0..empties{
-            match order {
-                ByteOrder::BigEndian => {
-                    label_defs_raw.append(&mut 0u32.to_be_bytes().to_vec());
-                    label_defs_raw.append(&mut (labels_raw.len() as u32 + 0x32Cu32).to_be_bytes().to_vec());
-                }
-                ByteOrder::LittleEndian => {
-                    label_defs_raw.append(&mut 0u32.to_le_bytes().to_vec());
-                    label_defs_raw.append(&mut (labels_raw.len() as u32 + 0x32Cu32).to_le_bytes().to_vec());
-                }
-            }
+    /// Writes the labels of `msbt_strings` as a hash table of `slots` buckets.
+    pub fn write_to<W: Write + Seek>(buffer: &mut W, msbt_strings: &[MSBTString], slots: u32, previous_labels: &[Label], order: bytestream::ByteOrder) -> Result<()> {
+        debug!("Formatting labels...");
+        // Labels keep their place in their bucket, new ones go after them
+        let previous: HashMap<&str, usize> = previous_labels.iter().enumerate().map(|(i, l)| (l.label.as_str(), i)).collect();
+        let mut sorted_strings: Vec<&MSBTString> = msbt_strings.iter().collect();
+        sorted_strings.sort_by_key(|s| previous.get(s.label.as_str()).copied().unwrap_or(usize::MAX));
+        let slots = slots.max(1);
+        let mut buckets = vec![Vec::<&MSBTString>::new(); slots as usize];
+        for string in sorted_strings {
+            buckets[Self::calculate_hash(&string.label, slots) as usize].push(string);
         }
 
         //binary tiem
-        let section_size = labels_raw.len() + label_defs_raw.len() + 4;
-        result.append(&mut b"LBL1".to_vec());
-        match order {
-            ByteOrder::BigEndian => {
-                result.append(&mut (section_size as u32).to_be_bytes().to_vec());
-                result.append(&mut vec![0,0,0,0,0,0,0,0]);
-                result.append(&mut 101_u32.to_be_bytes().to_vec());
-                result.append(&mut label_defs_raw.clone());
-                result.append(&mut labels_raw.clone());
+        write_section(buffer, b"LBL1", order, |buffer| {
+            slots.write_to(buffer, order)?;
+            //Labels start right after the hash table. Empty buckets point to where the next labels are.
+            let mut offset = 4 + slots*8;
+            for bucket in &buckets {
+                (bucket.len() as u32).write_to(buffer, order)?;
+                offset.write_to(buffer, order)?;
+                offset += bucket.iter().map(|s| s.label.len() as u32 + 5).sum::<u32>();
             }
-            ByteOrder::LittleEndian => {
-                result.append(&mut (section_size as u32).to_le_bytes().to_vec());
-                result.append(&mut vec![0,0,0,0,0,0,0,0]);
-                result.append(&mut 101_u32.to_le_bytes().to_vec());
-                result.append(&mut label_defs_raw.clone());
-                result.append(&mut labels_raw.clone());
+            for string in buckets.iter().flatten() {
+                (string.label.len() as u8).write_to(buffer, order)?;
+                buffer.write_all(string.label.as_bytes())?;
+                string.index.write_to(buffer, order)?;
             }
-        }
-        let padding = 16 - result.len() %16;
-        for _i in 0..padding{
-            result.push(0xD0);
-        }
-        println!("Formated labels.");
-
-        Ok(result)
+            Ok(())
+        })?;
+        debug!("Formated labels.");
+        Ok(())
     }
 
-    fn calculate_hash(label: String) -> u64{
+    /// Bucket a label goes in, for a hash table of `slots` buckets.
+    pub fn calculate_hash(label: &str, slots: u32) -> u32{
         let mut hash:u64 = 0;
         for char in label.as_bytes(){
-            hash = hash.wrapping_mul(0x492) + (*char) as u64 ;
+            hash = hash.wrapping_mul(0x492).wrapping_add((*char) as u64);
         }
-        (hash & 0xFFFFFFFF) % 101
+        ((hash & 0xFFFFFFFF) % slots as u64) as u32
     }
 }
\ No newline at end of file
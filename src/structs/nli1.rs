@@ -0,0 +1,83 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use crate::error::Result;
+use crate::msbt::MSBTString;
+use crate::structs::write_section;
+use bytestream::{StreamReader, StreamWriter};
+use log::debug;
+
+#[derive(Debug, Clone)]
+pub struct NLI1{ // Numeric IDs, used instead of (or along with) labels by some older titles.
+    _magic: Vec<u8>,
+    pub section_size: u32,
+    pub entry_amount: u32,
+    pub entries: Vec<NumericId>
+}
+
+#[derive(Debug, Clone)]
+pub struct NumericId{
+    pub id: u32,
+    pub string_index: u32
+}
+
+impl Default for NLI1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NLI1{
+    pub fn new() -> NLI1 {
+        NLI1 { _magic: b"NLI1".to_vec(), section_size: 0, entry_amount: 0, entries: Vec::<NumericId>::new() }
+    }
+
+    pub fn read_from<R: Read + Seek>(buffer: &mut R, order: bytestream::ByteOrder) -> Result<NLI1> {
+        debug!("Extracting numeric IDs...");
+        let block_start = buffer.stream_position()?;
+        let mut magic = vec![0u8;4];
+        buffer.read_exact(&mut magic)?;
+        if magic != b"NLI1" {
+            buffer.seek(SeekFrom::Current(-4))?;
+            debug!("No NLI1 section, continuing...");
+            return Ok(NLI1{ _magic: "NONE".as_bytes().to_vec(), section_size: 0, entry_amount: 0, entries: Vec::<NumericId>::new() });
+        }
+        let section_size = u32::read_from(buffer, order)?;
+        buffer.seek(SeekFrom::Current(8))?;
+        let entry_amount = u32::read_from(buffer, order)?;
+        let mut entries = Vec::<NumericId>::new();
+        for _i in 0..entry_amount {
+            let id = u32::read_from(buffer, order)?;
+            let string_index = u32::read_from(buffer, order)?;
+            entries.push(NumericId { id, string_index });
+        }
+        let leftover_padding = if section_size%0x10 != 0 {0x10-(section_size%0x10)} else {0};
+        buffer.seek(SeekFrom::Start(block_start+0x10+section_size as u64+(leftover_padding) as u64))?;
+        debug!("Extracted numeric IDs.");
+        Ok(NLI1 {
+            _magic: magic,
+            section_size,
+            entry_amount,
+            entries
+        })
+    }
+
+    /// IDs that were already in `previous_entries` keep their position, new ones are added after them, by ID.
+    pub fn write_to<W: Write + Seek>(buffer: &mut W, msbt_strings: &[MSBTString], previous_entries: &[NumericId], order: bytestream::ByteOrder) -> Result<()>{
+        debug!("Formatting numeric IDs...");
+        let mut entries: Vec<NumericId> = msbt_strings.iter()
+            .filter_map(|s| s.id.map(|id| NumericId{ id, string_index: s.index }))
+            .collect();
+        entries.sort_by_key(|e| (previous_entries.iter().position(|p| p.id == e.id).unwrap_or(usize::MAX), e.id));
+        //binary tiem
+        write_section(buffer, b"NLI1", order, |buffer| {
+            (entries.len() as u32).write_to(buffer, order)?;
+            for entry in &entries {
+                entry.id.write_to(buffer, order)?;
+                entry.string_index.write_to(buffer, order)?;
+            }
+            Ok(())
+        })?;
+        debug!("Formated numeric IDs.");
+        Ok(())
+    }
+}
\ No newline at end of file
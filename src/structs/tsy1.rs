@@ -0,0 +1,67 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use crate::error::Result;
+use crate::msbt::MSBTString;
+use crate::structs::write_section;
+use bytestream::{StreamReader, StreamWriter};
+use log::debug;
+
+#[derive(Debug, Clone)]
+pub struct TSY1{ // Text styles, one index in the project's style list per string.
+    _magic: Vec<u8>,
+    pub section_size: u32,
+    pub styles: Vec<u32> // In TXT2 order
+}
+
+impl Default for TSY1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TSY1{
+    pub fn new() -> TSY1 {
+        TSY1 { _magic: b"TSY1".to_vec(), section_size: 0, styles: Vec::<u32>::new() }
+    }
+
+    pub fn read_from<R: Read + Seek>(buffer: &mut R, order: bytestream::ByteOrder) -> Result<TSY1> {
+        debug!("Extracting styles...");
+        let block_start = buffer.stream_position()?;
+        let mut magic = vec![0u8;4];
+        buffer.read_exact(&mut magic)?;
+        if magic != b"TSY1" {
+            buffer.seek(SeekFrom::Current(-4))?;
+            debug!("No TSY1 section, continuing...");
+            return Ok(TSY1{ _magic: "NONE".as_bytes().to_vec(), section_size: 0, styles: Vec::<u32>::new() });
+        }
+        let section_size = u32::read_from(buffer, order)?;
+        buffer.seek(SeekFrom::Current(8))?;
+        let mut styles = Vec::<u32>::new();
+        for _i in 0..section_size/4 {
+            styles.push(u32::read_from(buffer, order)?);
+        }
+        let leftover_padding = if section_size%0x10 != 0 {0x10-(section_size%0x10)} else {0};
+        buffer.seek(SeekFrom::Start(block_start+0x10+section_size as u64+(leftover_padding) as u64))?;
+        debug!("Extracted styles.");
+        Ok(TSY1 {
+            _magic: magic,
+            section_size,
+            styles
+        })
+    }
+
+    pub fn write_to<W: Write + Seek>(buffer: &mut W, msbt_strings: &[MSBTString], order: bytestream::ByteOrder) -> Result<()>{
+        debug!("Formatting styles...");
+        let mut new_strings: Vec<&MSBTString> = msbt_strings.iter().collect();
+        new_strings.sort_by_key(|s| s.index);
+        //binary tiem
+        write_section(buffer, b"TSY1", order, |buffer| {
+            for string in new_strings {
+                string.style.unwrap_or(0).write_to(buffer, order)?;
+            }
+            Ok(())
+        })?;
+        debug!("Formated styles.");
+        Ok(())
+    }
+}
\ No newline at end of file
@@ -0,0 +1,45 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use crate::error::Result;
+use crate::structs::{read_bytes, write_section};
+use bytestream::StreamReader;
+use log::debug;
+
+#[derive(Debug, Clone)]
+pub struct UnknownSection{ // Any section we don't parse (ATO1, vendor sections...), kept as-is.
+    pub magic: Vec<u8>,
+    pub section_size: u32,
+    pub data: Vec<u8>
+}
+
+impl UnknownSection{
+    pub fn new(magic: Vec<u8>, data: Vec<u8>) -> UnknownSection {
+        UnknownSection { magic, section_size: data.len() as u32, data }
+    }
+
+    pub fn read_from<R: Read + Seek>(buffer: &mut R, order: bytestream::ByteOrder) -> Result<UnknownSection> {
+        let block_start = buffer.stream_position()?;
+        let mut magic = vec![0u8;4];
+        buffer.read_exact(&mut magic)?;
+        debug!("Extracting unknown section {}...", String::from_utf8_lossy(&magic));
+        let section_size = u32::read_from(buffer, order)?;
+        buffer.seek(SeekFrom::Current(8))?;
+        let data = read_bytes(buffer, section_size as u64, &String::from_utf8_lossy(&magic))?;
+        let leftover_padding = if section_size%0x10 != 0 {0x10-(section_size%0x10)} else {0};
+        buffer.seek(SeekFrom::Start(block_start+0x10+section_size as u64+(leftover_padding) as u64))?;
+        debug!("Extracted unknown section.");
+        Ok(UnknownSection {
+            magic,
+            section_size,
+            data
+        })
+    }
+
+    pub fn write_to<W: Write + Seek>(&self, buffer: &mut W, order: bytestream::ByteOrder) -> Result<()>{
+        debug!("Formatting unknown section {}...", String::from_utf8_lossy(&self.magic));
+        //binary tiem
+        write_section(buffer, &self.magic, order, |buffer| Ok(buffer.write_all(&self.data)?))?;
+        debug!("Formated unknown section.");
+        Ok(())
+    }
+}
\ No newline at end of file